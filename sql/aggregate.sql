@@ -1,6 +1,52 @@
-CREATE AGGREGATE simple_array(size int, value DOUBLE PRECISION)
+CREATE AGGREGATE simple_array(value DOUBLE PRECISION)
 (
     stype=internal,
     sfunc=simple_array_trans,
     finalfunc=simple_array_final,
-)
+    combinefunc=simple_array_combine,
+    serialfunc=simple_array_serialize,
+    deserialfunc=simple_array_deserialize,
+    mstype=internal,
+    msfunc=simple_array_moving_trans,
+    minvfunc=simple_array_moving_inverse,
+    mfinalfunc=simple_array_moving_final,
+    parallel=safe
+);
+
+CREATE AGGREGATE simple_array(size int, value DOUBLE PRECISION)
+(
+    stype=internal,
+    sfunc=simple_array_sized_trans,
+    finalfunc=simple_array_final,
+    parallel=safe
+);
+
+CREATE AGGREGATE simple_array(size int, value DOUBLE PRECISION, truncate boolean)
+(
+    stype=internal,
+    sfunc=simple_array_sized_truncate_trans,
+    finalfunc=simple_array_final,
+    parallel=safe
+);
+
+CREATE AGGREGATE simple_array_ordered(value DOUBLE PRECISION, key DOUBLE PRECISION)
+(
+    stype=internal,
+    sfunc=simple_array_ordered_trans,
+    finalfunc=simple_array_ordered_final,
+    combinefunc=simple_array_ordered_combine,
+    serialfunc=simple_array_ordered_serialize,
+    deserialfunc=simple_array_ordered_deserialize,
+    parallel=safe
+);
+
+CREATE AGGREGATE simple_array_ordered(value DOUBLE PRECISION, key timestamp with time zone)
+(
+    stype=internal,
+    sfunc=simple_array_ordered_timestamptz_trans,
+    finalfunc=simple_array_ordered_timestamptz_final,
+    combinefunc=simple_array_ordered_timestamptz_combine,
+    serialfunc=simple_array_ordered_timestamptz_serialize,
+    deserialfunc=simple_array_ordered_timestamptz_deserialize,
+    parallel=safe
+);
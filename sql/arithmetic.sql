@@ -0,0 +1,80 @@
+-- element-wise arithmetic between two SimpleArrays of the same length, and
+-- between a SimpleArray and a float8
+
+CREATE OPERATOR + (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_add,
+    COMMUTATOR = +
+);
+
+CREATE OPERATOR + (
+    LEFTARG = SimpleArray,
+    RIGHTARG = DOUBLE PRECISION,
+    PROCEDURE = simple_array_add_float8,
+    COMMUTATOR = +
+);
+
+CREATE OPERATOR + (
+    LEFTARG = DOUBLE PRECISION,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = float8_add_simple_array,
+    COMMUTATOR = +
+);
+
+CREATE OPERATOR - (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_sub
+);
+
+CREATE OPERATOR - (
+    LEFTARG = SimpleArray,
+    RIGHTARG = DOUBLE PRECISION,
+    PROCEDURE = simple_array_sub_float8
+);
+
+CREATE OPERATOR - (
+    LEFTARG = DOUBLE PRECISION,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = float8_sub_simple_array
+);
+
+CREATE OPERATOR * (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_mul,
+    COMMUTATOR = *
+);
+
+CREATE OPERATOR * (
+    LEFTARG = SimpleArray,
+    RIGHTARG = DOUBLE PRECISION,
+    PROCEDURE = simple_array_mul_float8,
+    COMMUTATOR = *
+);
+
+CREATE OPERATOR * (
+    LEFTARG = DOUBLE PRECISION,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = float8_mul_simple_array,
+    COMMUTATOR = *
+);
+
+CREATE OPERATOR / (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_div
+);
+
+CREATE OPERATOR / (
+    LEFTARG = SimpleArray,
+    RIGHTARG = DOUBLE PRECISION,
+    PROCEDURE = simple_array_div_float8
+);
+
+CREATE OPERATOR / (
+    LEFTARG = DOUBLE PRECISION,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = float8_div_simple_array
+);
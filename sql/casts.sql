@@ -0,0 +1,5 @@
+CREATE CAST (DOUBLE PRECISION[] AS SimpleArray)
+    WITH FUNCTION simple_array_from_float8_array(DOUBLE PRECISION[]);
+
+CREATE CAST (SimpleArray AS DOUBLE PRECISION[])
+    WITH FUNCTION simple_array_to_float8_array(SimpleArray);
@@ -0,0 +1,17 @@
+CREATE OPERATOR || (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_concat
+);
+
+CREATE OPERATOR || (
+    LEFTARG = SimpleArray,
+    RIGHTARG = DOUBLE PRECISION,
+    PROCEDURE = append
+);
+
+CREATE OPERATOR || (
+    LEFTARG = DOUBLE PRECISION,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = prepend
+);
@@ -0,0 +1,40 @@
+-- set-like containment operators and the default GIN operator class, which
+-- indexes the distinct elements of each SimpleArray
+
+CREATE OPERATOR @> (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_contains,
+    COMMUTATOR = <@,
+    RESTRICT = contsel,
+    JOIN = contjoinsel
+);
+
+CREATE OPERATOR <@ (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_contained,
+    COMMUTATOR = @>,
+    RESTRICT = contsel,
+    JOIN = contjoinsel
+);
+
+CREATE OPERATOR && (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_overlaps,
+    COMMUTATOR = &&,
+    RESTRICT = contsel,
+    JOIN = contjoinsel
+);
+
+CREATE OPERATOR CLASS simple_array_gin_ops
+DEFAULT FOR TYPE SimpleArray USING gin AS
+    OPERATOR 1 &&,
+    OPERATOR 2 @>,
+    OPERATOR 3 <@,
+    FUNCTION 1 btfloat8cmp(DOUBLE PRECISION, DOUBLE PRECISION),
+    FUNCTION 2 simple_array_gin_extract_value(SimpleArray, internal),
+    FUNCTION 3 simple_array_gin_extract_query(SimpleArray, internal, smallint, internal, internal, internal, internal),
+    FUNCTION 4 simple_array_gin_consistent(internal, smallint, SimpleArray, integer, internal, internal, internal, internal),
+    STORAGE DOUBLE PRECISION;
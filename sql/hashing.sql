@@ -0,0 +1,20 @@
+-- the default hash operator class, which makes SimpleArray usable in hash
+-- joins, hash aggregates, and hash-partitioned tables. postgres 10 has no
+-- extended hash support function, and we don't build one for it
+
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 110000 THEN
+        EXECUTE 'CREATE OPERATOR CLASS simple_array_hash_ops
+        DEFAULT FOR TYPE SimpleArray USING hash AS
+            OPERATOR 1 =,
+            FUNCTION 1 simple_array_hash(SimpleArray),
+            FUNCTION 2 simple_array_hash_extended(SimpleArray, bigint)';
+    ELSE
+        EXECUTE 'CREATE OPERATOR CLASS simple_array_hash_ops
+        DEFAULT FOR TYPE SimpleArray USING hash AS
+            OPERATOR 1 =,
+            FUNCTION 1 simple_array_hash(SimpleArray)';
+    END IF;
+END
+$$;
@@ -0,0 +1,74 @@
+-- comparison operators and the default btree operator class, which make
+-- SimpleArray usable in ORDER BY, DISTINCT, GROUP BY, UNIQUE constraints and
+-- btree indexes
+
+CREATE OPERATOR = (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_eq,
+    COMMUTATOR = =,
+    NEGATOR = <>,
+    RESTRICT = eqsel,
+    JOIN = eqjoinsel,
+    HASHES,
+    MERGES
+);
+
+CREATE OPERATOR <> (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_ne,
+    COMMUTATOR = <>,
+    NEGATOR = =,
+    RESTRICT = neqsel,
+    JOIN = neqjoinsel
+);
+
+CREATE OPERATOR < (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_lt,
+    COMMUTATOR = >,
+    NEGATOR = >=,
+    RESTRICT = scalarltsel,
+    JOIN = scalarltjoinsel
+);
+
+CREATE OPERATOR <= (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_le,
+    COMMUTATOR = >=,
+    NEGATOR = >,
+    RESTRICT = scalarltsel,
+    JOIN = scalarltjoinsel
+);
+
+CREATE OPERATOR > (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_gt,
+    COMMUTATOR = <,
+    NEGATOR = <=,
+    RESTRICT = scalargtsel,
+    JOIN = scalargtjoinsel
+);
+
+CREATE OPERATOR >= (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_ge,
+    COMMUTATOR = <=,
+    NEGATOR = <,
+    RESTRICT = scalargtsel,
+    JOIN = scalargtjoinsel
+);
+
+CREATE OPERATOR CLASS simple_array_btree_ops
+DEFAULT FOR TYPE SimpleArray USING btree AS
+    OPERATOR 1 <,
+    OPERATOR 2 <=,
+    OPERATOR 3 =,
+    OPERATOR 4 >=,
+    OPERATOR 5 >,
+    FUNCTION 1 simple_array_cmp(SimpleArray, SimpleArray);
@@ -0,0 +1,19 @@
+-- pgx only generates the text in/out functions for SimpleArray, so we attach
+-- the binary send/receive functions after the fact. this form of ALTER TYPE
+-- only exists in postgres 13+, on older versions we have to update the
+-- catalog directly instead
+DO $$
+BEGIN
+    IF current_setting('server_version_num')::int >= 130000 THEN
+        EXECUTE 'ALTER TYPE SimpleArray SET (
+            SEND = simplearray_send,
+            RECEIVE = simplearray_recv
+        )';
+    ELSE
+        UPDATE pg_catalog.pg_type
+        SET typsend = 'simplearray_send'::regproc,
+            typreceive = 'simplearray_recv'::regproc
+        WHERE oid = 'SimpleArray'::regtype;
+    END IF;
+END
+$$;
@@ -0,0 +1,40 @@
+-- vector similarity operators. smaller is more similar for all of them, so
+-- `ORDER BY col <-> query LIMIT k` finds the nearest neighbors
+
+CREATE OPERATOR <-> (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = l2_distance,
+    COMMUTATOR = <->
+);
+
+CREATE OPERATOR <=> (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = cosine_distance,
+    COMMUTATOR = <=>
+);
+
+CREATE OPERATOR <#> (
+    LEFTARG = SimpleArray,
+    RIGHTARG = SimpleArray,
+    PROCEDURE = simple_array_negative_dot,
+    COMMUTATOR = <#>
+);
+
+-- the default GiST operator class, which lets `ORDER BY col <-> query LIMIT k`
+-- find the exact nearest neighbors using an index instead of a sequential
+-- scan. it's an R-tree, so it only helps for low-dimensional vectors, and it
+-- rejects SimpleArrays of more than 500 elements
+CREATE OPERATOR CLASS simple_array_gist_ops
+DEFAULT FOR TYPE SimpleArray USING gist AS
+    OPERATOR 1 <-> (SimpleArray, SimpleArray) FOR ORDER BY float_ops,
+    FUNCTION 1 simple_array_gist_consistent(internal, SimpleArray, smallint, oid, internal),
+    FUNCTION 2 simple_array_gist_union(internal, internal),
+    FUNCTION 3 simple_array_gist_compress(internal),
+    FUNCTION 4 simple_array_gist_decompress(internal),
+    FUNCTION 5 simple_array_gist_penalty(internal, internal, internal),
+    FUNCTION 6 simple_array_gist_picksplit(internal, internal),
+    FUNCTION 7 simple_array_gist_same(SimpleArray, SimpleArray, internal),
+    FUNCTION 8 simple_array_gist_distance(internal, SimpleArray, smallint, oid, internal),
+    STORAGE SimpleArray;
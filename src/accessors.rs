@@ -0,0 +1,129 @@
+use pgx::*;
+
+use crate::SimpleArray;
+
+#[pg_extern(immutable, parallel_safe)]
+fn length<'input>(array: SimpleArray<'input>) -> i32 {
+    array.0.data.len() as i32
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn first<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    array.0.data.first().cloned()
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn last<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    array.0.data.last().cloned()
+}
+
+// like index(), except negative indexes count back from the end, and an
+// out-of-range index is an error instead of NULL
+#[pg_extern(immutable, parallel_safe)]
+fn index_strict<'input>(array: SimpleArray<'input>, index: i32) -> f64 {
+    let data = array.0.data;
+    let position = resolve_index(data.len(), index);
+    if position < 0 || position >= data.len() as i64 {
+        error!(
+            "index {} is out of bounds for SimpleArray of length {}",
+            index,
+            data.len()
+        )
+    }
+    data[position as usize]
+}
+
+// returns the elements in [start, end) as a new SimpleArray. as with index_strict
+// negative bounds count back from the end, but out-of-range bounds are clamped
+// to the array
+#[pg_extern(immutable, parallel_safe)]
+fn slice<'input>(array: SimpleArray<'input>, start: i32, end: i32) -> SimpleArray<'static> {
+    let data = array.0.data;
+    let clamp = |bound| resolve_index(data.len(), bound).max(0).min(data.len() as i64) as usize;
+    let (start, end) = (clamp(start), clamp(end));
+    if start >= end {
+        return SimpleArray::from_values(&[]);
+    }
+    SimpleArray::from_values(&data[start..end])
+}
+
+// converts an index that may be negative into an offset from the start
+fn resolve_index(len: usize, index: i32) -> i64 {
+    if index < 0 {
+        len as i64 + index as i64
+    } else {
+        index as i64
+    }
+}
+
+// returns one row per element; WITH ORDINALITY is handled by postgres itself
+#[pg_extern(immutable, parallel_safe)]
+fn unnest<'input>(
+    array: SimpleArray<'input>,
+) -> impl std::iter::Iterator<Item = f64> + 'input {
+    array.0.data.iter().cloned()
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_accessors() {
+        Spi::execute(|client| {
+            let (length, first) = client.select("SELECT length(a), first(a) FROM (SELECT '[1.0, 2.0, 3.0, 4.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_two::<i32, f64>();
+            assert_eq!(length, Some(4));
+            assert_eq!(first, Some(1.0));
+
+            let (last, from_end) = client.select("SELECT last(a), index_strict(a, -2) FROM (SELECT '[1.0, 2.0, 3.0, 4.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(last, Some(4.0));
+            assert_eq!(from_end, Some(3.0));
+
+            let (empty_first, empty_last) = client.select("SELECT first(a), last(a) FROM (SELECT '[]'::SimpleArray a) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(empty_first, None);
+            assert_eq!(empty_last, None);
+
+            let slices = client.select("SELECT slice(a, 1, 3)::text, slice(a, -2, 100)::text, slice(a, 3, 1)::text FROM (SELECT '[1.0, 2.0, 3.0, 4.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(slices, (
+                Some("[2.0, 3.0]".to_string()),
+                Some("[3.0, 4.0]".to_string()),
+                Some("[]".to_string()),
+            ));
+        })
+    }
+
+    #[pg_test(error = "index 4 is out of bounds for SimpleArray of length 4")]
+    fn test_index_strict_out_of_bounds() {
+        Spi::get_one::<f64>("SELECT index_strict('[1.0, 2.0, 3.0, 4.0]'::SimpleArray, 4)");
+    }
+
+    #[pg_test]
+    fn test_unnest() {
+        Spi::execute(|client| {
+            let (count, sum) = client.select("SELECT count(*)::float8, sum(v) FROM unnest('[1.0, 2.0, 4.5]'::SimpleArray) v", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(count, Some(3.0));
+            assert_eq!(sum, Some(7.5));
+
+            let (value, ordinality) = client.select("SELECT v, n FROM unnest('[1.0, 2.0, 4.5]'::SimpleArray) WITH ORDINALITY AS t(v, n) ORDER BY n DESC LIMIT 1", None, None)
+                .first()
+                .get_two::<f64, i64>();
+            assert_eq!(value, Some(4.5));
+            assert_eq!(ordinality, Some(3));
+
+            let count = client.select("SELECT count(*) FROM unnest('[]'::SimpleArray)", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(count, Some(0));
+        })
+    }
+}
@@ -21,3 +21,162 @@ pub fn aggregate_mctx(fcinfo: pg_sys::FunctionCallInfo) -> Option<pg_sys::Memory
         return Some(mctx);
     }
 }
+
+// postgres names for the rust types that can be aggregate arguments
+pub trait SqlType {
+    const NAME: &'static str;
+}
+
+impl SqlType for f64 {
+    const NAME: &'static str = "DOUBLE PRECISION";
+}
+
+impl SqlType for i32 {
+    const NAME: &'static str = "int";
+}
+
+impl SqlType for bool {
+    const NAME: &'static str = "boolean";
+}
+
+// a CREATE AGGREGATE statement, built by the `aggregate!` macro from the rust
+// functions that implement the aggregate
+pub struct AggregateDef {
+    pub name: &'static str,
+    pub args: &'static [(&'static str, &'static str)],
+    pub sfunc: &'static str,
+    pub finalfunc: &'static str,
+    pub combinefunc: Option<&'static str>,
+    pub serialfunc: Option<&'static str>,
+    pub deserialfunc: Option<&'static str>,
+    pub msfunc: Option<&'static str>,
+    pub minvfunc: Option<&'static str>,
+    pub mfinalfunc: Option<&'static str>,
+}
+
+impl AggregateDef {
+    pub fn to_sql(&self) -> String {
+        let args: Vec<_> = self
+            .args
+            .iter()
+            .map(|(name, typ)| format!("{} {}", name, typ))
+            .collect();
+
+        let mut options = vec![
+            "stype=internal".to_string(),
+            format!("sfunc={}", self.sfunc),
+            format!("finalfunc={}", self.finalfunc),
+        ];
+        if let Some(combinefunc) = self.combinefunc {
+            options.push(format!("combinefunc={}", combinefunc));
+        }
+        if let Some(serialfunc) = self.serialfunc {
+            options.push(format!("serialfunc={}", serialfunc));
+        }
+        if let Some(deserialfunc) = self.deserialfunc {
+            options.push(format!("deserialfunc={}", deserialfunc));
+        }
+        if let (Some(msfunc), Some(minvfunc), Some(mfinalfunc)) =
+            (self.msfunc, self.minvfunc, self.mfinalfunc)
+        {
+            options.push("mstype=internal".to_string());
+            options.push(format!("msfunc={}", msfunc));
+            options.push(format!("minvfunc={}", minvfunc));
+            options.push(format!("mfinalfunc={}", mfinalfunc));
+        }
+        options.push("parallel=safe".to_string());
+
+        format!(
+            "CREATE AGGREGATE {}({})\n(\n    {}\n);\n",
+            self.name,
+            args.join(", "),
+            options.join(",\n    "),
+        )
+    }
+}
+
+// the contents sql/aggregate.sql should have
+pub fn render_aggregates(aggregates: &[AggregateDef]) -> String {
+    let aggregates: Vec<_> = aggregates.iter().map(AggregateDef::to_sql).collect();
+    aggregates.join("\n")
+}
+
+macro_rules! __aggregate_opt {
+    () => { None };
+    ($value:expr) => { Some($value) };
+}
+
+// declares an aggregate in terms of the rust functions implementing it, e.g.
+// ```
+// aggregate! {
+//     const MY_AGGREGATE = my_agg(value: f64) {
+//         state: Vec<f64>,
+//         sfunc: my_agg_trans,
+//         finalfunc: my_agg_final -> MyOutput,
+//     }
+// }
+// ```
+// the functions' signatures are checked at compile time against the arguments
+// and state, so the generated CREATE AGGREGATE can't drift from the rust code
+macro_rules! aggregate {
+    (
+        $vis:vis const $const_name:ident = $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) {
+            state: $state:ty,
+            sfunc: $sfunc:ident,
+            finalfunc: $finalfunc:ident -> $output:ty,
+            $(
+                combinefunc: $combinefunc:ident,
+                serialfunc: $serialfunc:ident,
+                deserialfunc: $deserialfunc:ident,
+            )?
+            $(
+                moving: {
+                    state: $mstate:ty,
+                    msfunc: $msfunc:ident,
+                    minvfunc: $minvfunc:ident,
+                    mfinalfunc: $mfinalfunc:ident,
+                },
+            )?
+        }
+    ) => {
+        $vis const $const_name: $crate::aggregate_utils::AggregateDef =
+            $crate::aggregate_utils::AggregateDef {
+                name: stringify!($name),
+                args: &[
+                    $((stringify!($arg), <$arg_ty as $crate::aggregate_utils::SqlType>::NAME)),*
+                ],
+                sfunc: stringify!($sfunc),
+                finalfunc: stringify!($finalfunc),
+                combinefunc: __aggregate_opt!($(stringify!($combinefunc))?),
+                serialfunc: __aggregate_opt!($(stringify!($serialfunc))?),
+                deserialfunc: __aggregate_opt!($(stringify!($deserialfunc))?),
+                msfunc: __aggregate_opt!($(stringify!($msfunc))?),
+                minvfunc: __aggregate_opt!($(stringify!($minvfunc))?),
+                mfinalfunc: __aggregate_opt!($(stringify!($mfinalfunc))?),
+            };
+
+        const _: () = {
+            use $crate::palloc::Internal;
+            use pgx::pg_sys::FunctionCallInfo;
+
+            type TransFn<State> = fn(Option<Internal<State>>, $($arg_ty,)* FunctionCallInfo)
+                -> Option<Internal<State>>;
+
+            let _: TransFn<$state> = $sfunc;
+            let _: fn(Option<Internal<$state>>, FunctionCallInfo) -> Option<$output> = $finalfunc;
+            $(
+                let _: fn(Option<Internal<$state>>, Option<Internal<$state>>, FunctionCallInfo)
+                    -> Option<Internal<$state>> = $combinefunc;
+                let _: fn(Internal<$state>) -> Vec<u8> = $serialfunc;
+                let _: fn(Option<&[u8]>, Option<Internal<()>>, FunctionCallInfo)
+                    -> Option<Internal<$state>> = $deserialfunc;
+            )?
+            $(
+                let _: TransFn<$mstate> = $msfunc;
+                let _: TransFn<$mstate> = $minvfunc;
+                let _: fn(Option<Internal<$mstate>>, FunctionCallInfo) -> Option<$output> =
+                    $mfinalfunc;
+            )?
+        };
+    };
+}
@@ -0,0 +1,135 @@
+use pgx::*;
+
+use crate::SimpleArray;
+
+// element-wise arithmetic, the operators are defined in sql/arithmetic.sql.
+// division by zero is an error, as it is for float8
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_add<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> SimpleArray<'static> {
+    zip_with("add", a, b, |a, b| a + b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_sub<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> SimpleArray<'static> {
+    zip_with("subtract", a, b, |a, b| a - b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_mul<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> SimpleArray<'static> {
+    zip_with("multiply", a, b, |a, b| a * b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_div<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> SimpleArray<'static> {
+    zip_with("divide", a, b, divide)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_add_float8<'input>(a: SimpleArray<'input>, b: f64) -> SimpleArray<'static> {
+    map(a, |a| a + b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_sub_float8<'input>(a: SimpleArray<'input>, b: f64) -> SimpleArray<'static> {
+    map(a, |a| a - b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_mul_float8<'input>(a: SimpleArray<'input>, b: f64) -> SimpleArray<'static> {
+    map(a, |a| a * b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_div_float8<'input>(a: SimpleArray<'input>, b: f64) -> SimpleArray<'static> {
+    map(a, |a| divide(a, b))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn float8_add_simple_array<'input>(a: f64, b: SimpleArray<'input>) -> SimpleArray<'static> {
+    map(b, |b| a + b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn float8_sub_simple_array<'input>(a: f64, b: SimpleArray<'input>) -> SimpleArray<'static> {
+    map(b, |b| a - b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn float8_mul_simple_array<'input>(a: f64, b: SimpleArray<'input>) -> SimpleArray<'static> {
+    map(b, |b| a * b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn float8_div_simple_array<'input>(a: f64, b: SimpleArray<'input>) -> SimpleArray<'static> {
+    map(b, |b| divide(a, b))
+}
+
+fn zip_with(
+    operation: &str,
+    a: SimpleArray<'_>,
+    b: SimpleArray<'_>,
+    f: impl Fn(f64, f64) -> f64,
+) -> SimpleArray<'static> {
+    let (a, b) = (a.0.data, b.0.data);
+    if a.len() != b.len() {
+        error!(
+            "cannot {} SimpleArrays of different lengths ({} and {})",
+            operation,
+            a.len(),
+            b.len()
+        )
+    }
+    let values: Vec<f64> = a.iter().zip(b).map(|(a, b)| f(*a, *b)).collect();
+    SimpleArray::from_values(&values)
+}
+
+fn map(a: SimpleArray<'_>, f: impl Fn(f64) -> f64) -> SimpleArray<'static> {
+    let values: Vec<f64> = a.0.data.iter().map(|a| f(*a)).collect();
+    SimpleArray::from_values(&values)
+}
+
+fn divide(a: f64, b: f64) -> f64 {
+    if b == 0.0 {
+        error!("division by zero")
+    }
+    a / b
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_arithmetic() {
+        Spi::execute(|client| {
+            let results = client.select("SELECT (a + b)::text, (a - b)::text, (a * b)::text FROM (SELECT '[1.0, 2.0, 3.0]'::SimpleArray a, '[4.0, 5.0, 6.0]'::SimpleArray b) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(results, (
+                Some("[5.0, 7.0, 9.0]".to_string()),
+                Some("[-3.0, -3.0, -3.0]".to_string()),
+                Some("[4.0, 10.0, 18.0]".to_string()),
+            ));
+
+            let results = client.select("SELECT (a / 2.0)::text, (2.0 - a)::text, (12.0 / a)::text FROM (SELECT '[1.0, 2.0, 3.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(results, (
+                Some("[0.5, 1.0, 1.5]".to_string()),
+                Some("[1.0, 0.0, -1.0]".to_string()),
+                Some("[12.0, 6.0, 4.0]".to_string()),
+            ));
+        })
+    }
+
+    #[pg_test(error = "cannot add SimpleArrays of different lengths (3 and 2)")]
+    fn test_arithmetic_length_mismatch() {
+        Spi::get_one::<String>("SELECT ('[1.0, 2.0, 3.0]'::SimpleArray + '[1.0, 2.0]'::SimpleArray)::text");
+    }
+
+    #[pg_test(error = "division by zero")]
+    fn test_division_by_zero() {
+        Spi::get_one::<String>("SELECT ('[1.0, 2.0]'::SimpleArray / '[1.0, 0.0]'::SimpleArray)::text");
+    }
+}
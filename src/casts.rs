@@ -0,0 +1,80 @@
+use pgx::*;
+
+use crate::SimpleArray;
+
+// casts between SimpleArray and float8[], registered in sql/casts.sql
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_from_float8_array(
+    array: Array<f64>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> SimpleArray<'static> {
+    // pgx's Array flattens all the dimensions together, so we need to look at
+    // the raw ArrayType to see how many there are
+    let dimensions = unsafe {
+        let datum = pg_getarg_datum_raw(fcinfo, 0);
+        let raw = pg_sys::pg_detoast_datum(datum as *mut pg_sys::varlena) as *mut pg_sys::ArrayType;
+        (*raw).ndim
+    };
+    if dimensions > 1 {
+        error!(
+            "cannot cast a {}-dimensional array to SimpleArray, only one-dimensional arrays are supported",
+            dimensions
+        )
+    }
+
+    let values: Vec<f64> = array
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            value.unwrap_or_else(|| {
+                error!(
+                    "cannot cast an array containing NULLs to SimpleArray, element {} is NULL",
+                    i + 1
+                )
+            })
+        })
+        .collect();
+
+    SimpleArray::from_values(&values)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_to_float8_array<'input>(array: SimpleArray<'input>) -> Vec<f64> {
+    array.0.data.to_vec()
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_float8_array_casts() {
+        Spi::execute(|client| {
+            let value = client.select("SELECT index(ARRAY[1.5, 2.5, 'NaN']::float8[]::SimpleArray, 1)", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(value, Some(2.5));
+
+            let values = client.select("SELECT '[1.0, -2.0, inf]'::SimpleArray::float8[]", None, None)
+                .first()
+                .get_one::<Vec<f64>>();
+            assert_eq!(values, Some(vec![1.0, -2.0, f64::INFINITY]));
+
+            let text = client.select("SELECT '{}'::float8[]::SimpleArray::text", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[]"));
+        })
+    }
+
+    #[pg_test(error = "cannot cast an array containing NULLs to SimpleArray, element 2 is NULL")]
+    fn test_float8_array_cast_null() {
+        Spi::get_one::<String>("SELECT ARRAY[1.0, NULL]::float8[]::SimpleArray::text");
+    }
+
+    #[pg_test(error = "cannot cast a 2-dimensional array to SimpleArray, only one-dimensional arrays are supported")]
+    fn test_float8_array_cast_multidimensional() {
+        Spi::get_one::<String>("SELECT ARRAY[[1.0, 2.0], [3.0, 4.0]]::float8[]::SimpleArray::text");
+    }
+}
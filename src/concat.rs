@@ -0,0 +1,77 @@
+use pgx::*;
+
+use crate::SimpleArray;
+
+// concatenation functions, all of which are also available as `||`, see
+// sql/concat.sql
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_concat<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> SimpleArray<'static> {
+    let (a, b) = (a.0.data, b.0.data);
+    let mut values = Vec::with_capacity(a.len() + b.len());
+    values.extend_from_slice(a);
+    values.extend_from_slice(b);
+    SimpleArray::from_values(&values)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn append<'input>(array: SimpleArray<'input>, value: f64) -> SimpleArray<'static> {
+    let data = array.0.data;
+    let mut values = Vec::with_capacity(data.len() + 1);
+    values.extend_from_slice(data);
+    values.push(value);
+    SimpleArray::from_values(&values)
+}
+
+// takes its arguments in the same order as postgres's array_prepend()
+#[pg_extern(immutable, parallel_safe)]
+fn prepend<'input>(value: f64, array: SimpleArray<'input>) -> SimpleArray<'static> {
+    let data = array.0.data;
+    let mut values = Vec::with_capacity(data.len() + 1);
+    values.push(value);
+    values.extend_from_slice(data);
+    SimpleArray::from_values(&values)
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_concat() {
+        Spi::execute(|client| {
+            let results = client.select("SELECT (a || '[3.0]')::text, (a || 3.0)::text, (0.0 || a)::text FROM (SELECT '[1.0, 2.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(results, (
+                Some("[1.0, 2.0, 3.0]".to_string()),
+                Some("[1.0, 2.0, 3.0]".to_string()),
+                Some("[0.0, 1.0, 2.0]".to_string()),
+            ));
+
+            let results = client.select("SELECT append(a, 1.0)::text, prepend(1.0, a)::text, (a || a)::text FROM (SELECT '[]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(results, (
+                Some("[1.0]".to_string()),
+                Some("[1.0]".to_string()),
+                Some("[]".to_string()),
+            ));
+        })
+    }
+
+    #[pg_test]
+    fn test_concat_update() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE series (id int, readings SimpleArray)", None, None);
+            client.update("INSERT INTO series VALUES (1, '[1.0]')", None, None);
+            client.update("UPDATE series SET readings = readings || 2.0 WHERE id = 1", None, None);
+            client.update("UPDATE series SET readings = readings || '[3.0, 4.0]' WHERE id = 1", None, None);
+
+            let readings = client.select("SELECT readings::text FROM series WHERE id = 1", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(readings.as_deref(), Some("[1.0, 2.0, 3.0, 4.0]"));
+        })
+    }
+}
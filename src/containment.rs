@@ -0,0 +1,214 @@
+use std::{cmp::Ordering, mem::size_of, slice};
+
+use pgx::*;
+
+use crate::{palloc::raw::Internal, stats::float_cmp, SimpleArray};
+
+// set-like containment operators treating each SimpleArray as the set of its
+// elements, along with the GIN operator class that indexes them; both are
+// defined in sql/containment.sql. elements are compared the way `=` compares
+// them, so NaN matches NaN and -0.0 matches 0.0
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_contains<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    let a = sorted_distinct(a.0.data);
+    b.0.data.iter().all(|value| search(&a, *value))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_contained<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    simple_array_contains(b, a)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_overlaps<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    let a = sorted_distinct(a.0.data);
+    b.0.data.iter().any(|value| search(&a, *value))
+}
+
+// approximate versions of the above, where elements match if they're within
+// `epsilon` of each other. these can't use the index
+
+#[pg_extern(immutable, parallel_safe)]
+fn contains_within<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>, epsilon: f64) -> bool {
+    check_epsilon(epsilon);
+    let a = a.0.data;
+    b.0.data.iter().all(|b| a.iter().any(|a| within(*a, *b, epsilon)))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn contained_within<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>, epsilon: f64) -> bool {
+    contains_within(b, a, epsilon)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn overlaps_within<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>, epsilon: f64) -> bool {
+    check_epsilon(epsilon);
+    let a = a.0.data;
+    b.0.data.iter().any(|b| a.iter().any(|a| within(*a, *b, epsilon)))
+}
+
+fn check_epsilon(epsilon: f64) {
+    if epsilon.is_nan() || epsilon < 0.0 {
+        error!("epsilon must be a non-negative number, got {}", epsilon)
+    }
+}
+
+fn within(a: f64, b: f64, epsilon: f64) -> bool {
+    float_cmp(a, b) == Ordering::Equal || (a - b).abs() <= epsilon
+}
+
+fn sorted_distinct(data: &[f64]) -> Vec<f64> {
+    let mut values = data.to_vec();
+    values.sort_by(|a, b| float_cmp(*a, *b));
+    values.dedup_by(|a, b| float_cmp(*a, *b) == Ordering::Equal);
+    values
+}
+
+fn search(sorted: &[f64], value: f64) -> bool {
+    sorted.binary_search_by(|probe| float_cmp(*probe, value)).is_ok()
+}
+
+// GIN support functions. the keys are the distinct elements of the array,
+// stored as float8 and compared with btfloat8cmp, and the strategy numbers
+// match the ones used by the built-in array operator class
+const OVERLAP_STRATEGY: i16 = 1;
+const CONTAINS_STRATEGY: i16 = 2;
+const CONTAINED_STRATEGY: i16 = 3;
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gin_extract_value<'input>(
+    array: SimpleArray<'input>,
+    nkeys: Internal<i32>,
+) -> Internal<pg_sys::Datum> {
+    unsafe { extract_keys(array, nkeys) }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gin_extract_query<'input>(
+    query: SimpleArray<'input>,
+    nkeys: Internal<i32>,
+    strategy: i16,
+    _pmatch: Option<Internal<()>>,
+    _extra_data: Option<Internal<()>>,
+    _null_flags: Option<Internal<()>>,
+    mut search_mode: Internal<i32>,
+) -> Internal<pg_sys::Datum> {
+    let is_empty = query.0.data.is_empty();
+    *search_mode = match strategy {
+        // every array contains the empty set
+        CONTAINS_STRATEGY if is_empty => pg_sys::GIN_SEARCH_MODE_ALL as i32,
+        // the empty array is contained in every query, despite having no keys
+        CONTAINED_STRATEGY => pg_sys::GIN_SEARCH_MODE_INCLUDE_EMPTY as i32,
+        OVERLAP_STRATEGY | CONTAINS_STRATEGY => pg_sys::GIN_SEARCH_MODE_DEFAULT as i32,
+        _ => error!("unrecognized strategy number: {}", strategy),
+    };
+    unsafe { extract_keys(query, nkeys) }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+#[allow(clippy::too_many_arguments)]
+fn simple_array_gin_consistent<'input>(
+    check: Internal<bool>,
+    strategy: i16,
+    _query: SimpleArray<'input>,
+    nkeys: i32,
+    _extra_data: Option<Internal<()>>,
+    mut recheck: Internal<bool>,
+    _query_keys: Option<Internal<()>>,
+    _null_flags: Option<Internal<()>>,
+) -> bool {
+    let check = unsafe { slice::from_raw_parts(check.0.as_ptr(), nkeys as usize) };
+    match strategy {
+        OVERLAP_STRATEGY => {
+            *recheck = false;
+            check.iter().any(|present| *present)
+        }
+        CONTAINS_STRATEGY => {
+            *recheck = false;
+            check.iter().all(|present| *present)
+        }
+        // the index can't tell us whether the array has elements that aren't
+        // in the query, so we need to recheck every candidate
+        CONTAINED_STRATEGY => {
+            *recheck = true;
+            true
+        }
+        _ => error!("unrecognized strategy number: {}", strategy),
+    }
+}
+
+// GIN expects the keys in a palloc'd array of Datums
+unsafe fn extract_keys(array: SimpleArray<'_>, mut nkeys: Internal<i32>) -> Internal<pg_sys::Datum> {
+    let values = sorted_distinct(array.0.data);
+    let keys = pg_sys::palloc(values.len() * size_of::<pg_sys::Datum>()) as *mut pg_sys::Datum;
+    for (i, value) in values.iter().enumerate() {
+        // canonicalize -0.0 so that the keys look the same as the ones `=`
+        // considers equal
+        let value = if *value == 0.0 { 0.0 } else { *value };
+        *keys.add(i) = value.into_datum().unwrap();
+    }
+    *nkeys = values.len() as i32;
+    Internal(std::ptr::NonNull::new_unchecked(keys))
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_containment_operators() {
+        Spi::execute(|client| {
+            let results = client.select("SELECT a @> '[3.0, 1.0, 1.0]', a <@ '[1.0, 2.0, 3.0, 4.0]', a && '[5.0, -0.0]' FROM (SELECT '[0.0, 1.0, 2.0, 3.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<bool, bool, bool>();
+            assert_eq!(results, (Some(true), Some(false), Some(true)));
+
+            let results = client.select("SELECT '[NaN]'::SimpleArray <@ '[1.0, NaN]', '[]'::SimpleArray <@ '[1.0]', '[1.0]'::SimpleArray && '[]'", None, None)
+                .first()
+                .get_three::<bool, bool, bool>();
+            assert_eq!(results, (Some(true), Some(true), Some(false)));
+
+            let results = client.select("SELECT contains_within(a, '[1.05]', 0.1), contained_within(a, '[1.0, 2.0]', 0.01), overlaps_within(a, '[5.0]', 1.0) FROM (SELECT '[1.0, 2.001]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<bool, bool, bool>();
+            assert_eq!(results, (Some(true), Some(true), Some(false)));
+        })
+    }
+
+    #[pg_test]
+    fn test_gin_opclass() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE tagged (tags SimpleArray)", None, None);
+            client.update("INSERT INTO tagged SELECT simple_array(i % 7) FROM generate_series(1, 1000) i GROUP BY i % 100", None, None);
+            client.update("INSERT INTO tagged VALUES ('[]'), ('[1.0]'), ('[1.0, 2.0]')", None, None);
+            client.update("CREATE INDEX tagged_tags ON tagged USING gin (tags)", None, None);
+            client.update("SET enable_seqscan = off", None, None);
+
+            let plan = client.select("EXPLAIN SELECT * FROM tagged WHERE tags @> '[1.0]'", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("tagged_tags"), "{}", plan);
+
+            for query in &[
+                "tags @> '[1.0, 2.0]'",
+                "tags @> '[]'",
+                "tags <@ '[1.0, 2.0]'",
+                "tags && '[2.0, 6.0]'",
+            ] {
+                client.update("SET enable_seqscan = off", None, None);
+                let indexed = client.select(&format!("SELECT count(*) FROM tagged WHERE {}", query), None, None)
+                    .first()
+                    .get_one::<i64>();
+                client.update("SET enable_seqscan = on", None, None);
+                client.update("SET enable_bitmapscan = off", None, None);
+                let sequential = client.select(&format!("SELECT count(*) FROM tagged WHERE {}", query), None, None)
+                    .first()
+                    .get_one::<i64>();
+                client.update("SET enable_bitmapscan = on", None, None);
+                assert_eq!(indexed, sequential, "{}", query);
+            }
+        })
+    }
+}
@@ -0,0 +1,119 @@
+use std::mem::size_of;
+
+use pgx::*;
+
+use crate::SimpleArray;
+
+// hash support functions, used by the hash operator class in sql/hashing.sql.
+// they must agree with `=`, so NaNs and zeros are canonicalized before hashing
+// the same way the comparison treats all NaNs, and -0.0 and 0.0, as equal
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_hash<'input>(array: SimpleArray<'input>) -> i32 {
+    let bytes = canonical_bytes(array.0.data);
+    unsafe { hash(&bytes) as i32 }
+}
+
+// the seeded hash used for hash partitioning. postgres 10 doesn't have
+// extended hash functions at all
+#[cfg(not(feature = "pg10"))]
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_hash_extended<'input>(array: SimpleArray<'input>, seed: i64) -> i64 {
+    let bytes = canonical_bytes(array.0.data);
+    unsafe { hash_extended(&bytes, seed as u64) as i64 }
+}
+
+// the len and data of the array, with each value canonicalized
+fn canonical_bytes(data: &[f64]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(size_of::<u32>() + data.len() * size_of::<f64>());
+    bytes.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+    for value in data {
+        bytes.extend_from_slice(&canonical(*value).to_bits().to_ne_bytes());
+    }
+    bytes
+}
+
+// maps all the values `=` considers equal to the same bits
+pub(crate) fn canonical(value: f64) -> f64 {
+    if value.is_nan() {
+        f64::NAN
+    } else if value == 0.0 {
+        0.0
+    } else {
+        value
+    }
+}
+
+// postgres 12 turned hash_any() into an inline wrapper around hash_bytes(), so
+// only the latter is available to us there
+#[cfg(any(feature = "pg10", feature = "pg11"))]
+unsafe fn hash(bytes: &[u8]) -> u32 {
+    pg_sys::hash_any(bytes.as_ptr(), bytes.len() as i32) as u32
+}
+
+#[cfg(not(any(feature = "pg10", feature = "pg11")))]
+unsafe fn hash(bytes: &[u8]) -> u32 {
+    pg_sys::hash_bytes(bytes.as_ptr(), bytes.len() as i32)
+}
+
+#[cfg(feature = "pg11")]
+unsafe fn hash_extended(bytes: &[u8], seed: u64) -> u64 {
+    pg_sys::hash_any_extended(bytes.as_ptr(), bytes.len() as i32, seed) as u64
+}
+
+#[cfg(not(any(feature = "pg10", feature = "pg11")))]
+unsafe fn hash_extended(bytes: &[u8], seed: u64) -> u64 {
+    pg_sys::hash_bytes_extended(bytes.as_ptr(), bytes.len() as i32, seed)
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_hash_consistent_with_equality() {
+        Spi::execute(|client| {
+            let (zeros, nans) = client.select("SELECT simple_array_hash('[-0.0, 1.0]') = simple_array_hash('[0.0, 1.0]'), simple_array_hash('[NaN]') = simple_array_hash('[-NaN]')", None, None)
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(zeros, Some(true));
+            assert_eq!(nans, Some(true));
+        })
+    }
+
+    #[cfg(not(feature = "pg10"))]
+    #[pg_test]
+    fn test_hash_extended_consistent_with_hash() {
+        Spi::execute(|client| {
+            let extended = client.select("SELECT simple_array_hash_extended('[1.0, 2.0]', 0)::bit(32) = simple_array_hash('[1.0, 2.0]')::bit(32)", None, None)
+                .first()
+                .get_one::<bool>();
+            assert_eq!(extended, Some(true));
+        })
+    }
+
+    // hash partitioning needs postgres 11+
+    #[cfg(not(feature = "pg10"))]
+    #[pg_test]
+    fn test_hash_opclass() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE hashed_arrays (a SimpleArray) PARTITION BY HASH (a)", None, None);
+            client.update("CREATE TABLE hashed_arrays_0 PARTITION OF hashed_arrays FOR VALUES WITH (MODULUS 2, REMAINDER 0)", None, None);
+            client.update("CREATE TABLE hashed_arrays_1 PARTITION OF hashed_arrays FOR VALUES WITH (MODULUS 2, REMAINDER 1)", None, None);
+            client.update("INSERT INTO hashed_arrays SELECT simple_array(i) FROM generate_series(1, 20) i GROUP BY i % 5", None, None);
+
+            client.update("SET enable_mergejoin = off", None, None);
+            client.update("SET enable_nestloop = off", None, None);
+            let plan = client.select("EXPLAIN SELECT * FROM hashed_arrays l JOIN hashed_arrays r ON l.a = r.a", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Hash Join"), "{}", plan);
+
+            let matches = client.select("SELECT count(*) FROM hashed_arrays l JOIN hashed_arrays r ON l.a = r.a", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(matches, Some(5));
+        })
+    }
+}
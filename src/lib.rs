@@ -1,18 +1,40 @@
-use std::slice;
+use std::{convert::TryInto, mem::size_of, slice};
 
 use pg_sys::Datum;
 use pgx::*;
 
 use flat_serialize::*;
 
-use aggregate_utils::in_aggregate_context;
-use palloc::Internal;
+use aggregate_utils::{aggregate_mctx, in_aggregate_context};
+use memory_accounting::{AccountedVec, AccountedVecDeque};
+use palloc::{in_memory_context, Internal};
 
+mod accessors;
+#[macro_use]
 mod aggregate_utils;
+mod arithmetic;
+mod casts;
+mod concat;
+mod containment;
+mod hashing;
+mod memory_accounting;
+mod ordered_aggregate;
+mod ordering;
 mod palloc;
+mod send_recv;
+mod similarity;
+mod stats;
+mod transform;
 
 pg_module_magic!();
 
+#[allow(non_snake_case)]
+#[pg_guard]
+pub extern "C" fn _PG_init() {
+    palloc::init();
+    memory_accounting::init();
+}
+
 // an example of using flat-serialize to create a simple array type,
 // represented as
 // ```
@@ -55,12 +77,62 @@ impl<'input> InOutFuncs for SimpleArray<'input> {
         let _ = write!(buffer, "{:?}", self.0.data);
     }
 
-    fn input(_input: &std::ffi::CStr) -> Self
+    fn input(input: &std::ffi::CStr) -> Self
     where
         Self: Sized,
     {
-        unimplemented!("we don't bother implementing string input")
+        let input = input
+            .to_str()
+            .unwrap_or_else(|_| error!("invalid input syntax for type simplearray: not UTF-8"));
+        // input accepts the same format output produces, so we can round-trip
+        // through text (COPY, pg_dump, etc.)
+        let values = match parse_values(input) {
+            Ok(values) => values,
+            Err((position, problem)) => error!(
+                "invalid input syntax for type simplearray: \"{}\" at position {}: {}",
+                input,
+                position + 1,
+                problem
+            ),
+        };
+        SimpleArray::from_values(&values)
+    }
+}
+
+// parses the `[1.0, 2.5, NaN, inf]` format that the debug output produces.
+// on failure returns the byte offset of the problem along with a description
+fn parse_values(input: &str) -> Result<Vec<f64>, (usize, &'static str)> {
+    let leading = input.len() - input.trim_start().len();
+    let trimmed = input.trim();
+    if !trimmed.starts_with('[') {
+        return Err((leading, "expected \"[\""));
+    }
+    if trimmed.len() < 2 || !trimmed.ends_with(']') {
+        return Err((leading + trimmed.len(), "expected \"]\""));
+    }
+
+    let body = &trimmed[1..trimmed.len() - 1];
+    if body.trim().is_empty() {
+        return Ok(vec![]);
     }
+
+    let mut values = vec![];
+    let mut offset = leading + 1;
+    for element in body.split(',') {
+        let position = offset + (element.len() - element.trim_start().len());
+        let value = element.trim();
+        if value.is_empty() {
+            return Err((position, "expected a number"));
+        }
+        // rust's float parsing handles exponents, `NaN`, and `inf`, which
+        // covers everything `{:?}` can output
+        match value.parse() {
+            Ok(value) => values.push(value),
+            Err(_) => return Err((position, "invalid number")),
+        }
+        offset += element.len() + 1;
+    }
+    Ok(values)
 }
 
 // shim code to convert from a datum into something rust understands, all
@@ -104,15 +176,15 @@ impl<'input> IntoDatum for SimpleArray<'input> {
 // a basic aggregate to construct a SimpleArray
 
 // the trans function just pushes onto a vector
-#[pg_extern]
+#[pg_extern(parallel_safe)]
 fn simple_array_trans(
-    state: Option<Internal<Vec<f64>>>,
+    state: Option<Internal<AccountedVec<f64>>>,
     value: f64,
     fcinfo: pg_sys::FunctionCallInfo,
-) -> Option<Internal<Vec<f64>>> {
+) -> Option<Internal<AccountedVec<f64>>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
-            let mut state = state.unwrap_or_else(|| vec![].into());
+            let mut state = state.unwrap_or_else(|| Internal::new_owned(AccountedVec::new()));
 
             state.push(value);
 
@@ -121,6 +193,123 @@ fn simple_array_trans(
     }
 }
 
+// when the number of values is known ahead of time, the sized variants of the
+// trans function reserve space for all of them on the first call, avoiding
+// reallocations as the vector grows. when more values than declared arrive
+// they either error or, if `truncate` is set, drop the excess values.
+//
+// the size is user input, so the up-front reservation is capped at
+// MAX_RESERVED_VALUES; larger arrays grow as normal past that point
+const MAX_RESERVED_VALUES: usize = 1 << 16;
+
+#[pg_extern(parallel_safe)]
+fn simple_array_sized_trans(
+    state: Option<Internal<AccountedVec<f64>>>,
+    size: i32,
+    value: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVec<f64>>> {
+    unsafe { push_sized(state, size, value, false, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_sized_truncate_trans(
+    state: Option<Internal<AccountedVec<f64>>>,
+    size: i32,
+    value: f64,
+    truncate: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVec<f64>>> {
+    unsafe { push_sized(state, size, value, truncate, fcinfo) }
+}
+
+unsafe fn push_sized(
+    state: Option<Internal<AccountedVec<f64>>>,
+    size: i32,
+    value: f64,
+    truncate: bool,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVec<f64>>> {
+    in_aggregate_context(fcinfo, || {
+        if size < 0 {
+            error!("simple_array size must not be negative, got {}", size)
+        }
+        let size = size as usize;
+        let mut state = state.unwrap_or_else(|| {
+            Internal::new_owned(AccountedVec::with_capacity(size.min(MAX_RESERVED_VALUES)))
+        });
+
+        if state.len() >= size {
+            if truncate {
+                return Some(state);
+            }
+            error!("simple_array received more than the declared {} values", size)
+        }
+        state.push(value);
+
+        Some(state)
+    })
+}
+
+// to support parallel aggregation we need a combine function that merges the
+// states from two workers, along with functions to send the states between
+// processes as bytea
+
+#[pg_extern(parallel_safe)]
+fn simple_array_combine(
+    state1: Option<Internal<AccountedVec<f64>>>,
+    state2: Option<Internal<AccountedVec<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVec<f64>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || match (state1, state2) {
+            (None, None) => None,
+            // the result must live in the aggregate context, so we can't just
+            // return the second state
+            (None, Some(state2)) => Some(Internal::new_owned((*state2).clone())),
+            (Some(state1), None) => Some(state1),
+            (Some(mut state1), Some(state2)) => {
+                state1.extend_from_slice(&state2);
+                Some(state1)
+            }
+        })
+    }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_serialize(state: Internal<AccountedVec<f64>>) -> Vec<u8> {
+    // serialized states are only ever sent between processes of the same
+    // server, so native endianness is fine
+    let mut bytes = Vec::with_capacity(state.len() * size_of::<f64>());
+    for value in state.iter() {
+        bytes.extend_from_slice(&value.to_ne_bytes());
+    }
+    bytes
+}
+
+// the dummy internal argument means this can't be strict, so a worker that saw
+// no rows, and therefore sends back a NULL state, gets passed in as NULL bytes
+#[pg_extern(parallel_safe)]
+fn simple_array_deserialize(
+    bytes: Option<&[u8]>,
+    _internal: Option<Internal<()>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVec<f64>>> {
+    let bytes = bytes?;
+    if bytes.len() % size_of::<f64>() != 0 {
+        error!("invalid serialized simple_array state of {} bytes", bytes.len())
+    }
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let state: Vec<f64> = bytes
+                .chunks_exact(size_of::<f64>())
+                .map(|value| f64::from_ne_bytes(value.try_into().unwrap()))
+                .collect();
+            Some(Internal::new_owned(AccountedVec::from_vec(state)))
+        })
+    }
+}
+
 // ignore this code for now, it'll probably be library code
 macro_rules! flatten {
     ($typ:ident { $($field:ident: $value:expr),* $(,)? }) => {
@@ -140,36 +329,149 @@ macro_rules! flatten {
     }
 }
 
+impl SimpleArray<'static> {
+    // flatten a slice of values into a new buffer that contains the size, the
+    // data, and the varlen header, i.e. something that can be stored on disk.
+    // the buffer is leaked for postgres to free, so it's allocated in
+    // CurrentMemoryContext, same as a palloc
+    pub fn from_values(values: &[f64]) -> Self {
+        let flattened = unsafe {
+            in_memory_context(pg_sys::CurrentMemoryContext, || {
+                flatten! {
+                    SimpleArrayData{
+                        header: &0,
+                        data: values,
+                        // note the lack of length; because it is exactly the
+                        // length of a slice it will be computed from that
+                    }
+                }
+            })
+        };
+        SimpleArray(flattened)
+    }
+}
+
 // the final function flattens the vector into something that can be stored on
-// disk
-#[pg_extern]
+// disk. in a window the final function is called repeatedly on the same state,
+// so it must not modify the state, and allocates its output in the current,
+// per-call, memory context instead of the aggregate's so the outputs don't
+// accumulate for the lifetime of the window
+#[pg_extern(parallel_safe)]
 fn simple_array_final(
-    state: Option<Internal<Vec<f64>>>,
+    state: Option<Internal<AccountedVec<f64>>>,
     fcinfo: pg_sys::FunctionCallInfo,
 ) -> Option<SimpleArray<'static>> {
+    if aggregate_mctx(fcinfo).is_none() {
+        error!("cannot call as non-aggregate")
+    }
+    let state = match state {
+        None => return None,
+        Some(state) => state,
+    };
+    // we need to flatten the vector to a single buffer that contains
+    // both the size, the data, and the varlen header
+    SimpleArray::from_values(&state).into()
+}
+
+// moving-aggregate versions of the trans and final functions, used by window
+// frames that slide. rows leave the frame in the same order they entered it,
+// so we keep them in a deque, pushing new values onto the back and popping
+// removed ones off the front
+#[pg_extern(parallel_safe)]
+fn simple_array_moving_trans(
+    state: Option<Internal<AccountedVecDeque<f64>>>,
+    value: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVecDeque<f64>>> {
     unsafe {
         in_aggregate_context(fcinfo, || {
-            let state = match state {
-                None => return None,
-                Some(state) => state,
-            };
-            // we need to flatten the vector to a single buffer that contains
-            // both the size, the data, and the varlen header
-            let flattened = flatten! {
-                SimpleArrayData{
-                    header: &0,
-                    data: &state,
-                    // note the lack of length; because it is exactly the
-                    // length of a slice it will be computed from that
-                }
-            };
+            let mut state = state.unwrap_or_else(|| Internal::new_owned(AccountedVecDeque::new()));
+
+            state.push_back(value);
+
+            Some(state)
+        })
+    }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_moving_inverse(
+    state: Option<Internal<AccountedVecDeque<f64>>>,
+    _value: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<AccountedVecDeque<f64>>> {
+    unsafe {
+        in_aggregate_context(fcinfo, || {
+            let mut state = state?;
+
+            state.pop_front();
 
-            SimpleArray(flattened).into()
+            Some(state)
         })
     }
 }
 
-// finally an index function to get a value out of a simple array
+#[pg_extern(parallel_safe)]
+fn simple_array_moving_final(
+    state: Option<Internal<AccountedVecDeque<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SimpleArray<'static>> {
+    if aggregate_mctx(fcinfo).is_none() {
+        error!("cannot call as non-aggregate")
+    }
+    let state = match state {
+        None => return None,
+        Some(state) => state,
+    };
+    let values: Vec<f64> = state.iter().cloned().collect();
+    SimpleArray::from_values(&values).into()
+}
+
+// the CREATE AGGREGATE in sql/aggregate.sql is generated from this; if the
+// functions above change signature this will fail to compile
+aggregate! {
+    const SIMPLE_ARRAY_AGGREGATE = simple_array(value: f64) {
+        state: AccountedVec<f64>,
+        sfunc: simple_array_trans,
+        finalfunc: simple_array_final -> SimpleArray<'static>,
+        combinefunc: simple_array_combine,
+        serialfunc: simple_array_serialize,
+        deserialfunc: simple_array_deserialize,
+        moving: {
+            state: AccountedVecDeque<f64>,
+            msfunc: simple_array_moving_trans,
+            minvfunc: simple_array_moving_inverse,
+            mfinalfunc: simple_array_moving_final,
+        },
+    }
+}
+
+// the sized variants don't have a combine function; the size limit applies to
+// the aggregate as a whole, which no single worker could enforce
+aggregate! {
+    const SIMPLE_ARRAY_SIZED_AGGREGATE = simple_array(size: i32, value: f64) {
+        state: AccountedVec<f64>,
+        sfunc: simple_array_sized_trans,
+        finalfunc: simple_array_final -> SimpleArray<'static>,
+    }
+}
+
+aggregate! {
+    const SIMPLE_ARRAY_SIZED_TRUNCATE_AGGREGATE =
+        simple_array(size: i32, value: f64, truncate: bool) {
+        state: AccountedVec<f64>,
+        sfunc: simple_array_sized_truncate_trans,
+        finalfunc: simple_array_final -> SimpleArray<'static>,
+    }
+}
+
+// finally an index function to get a value out of a simple array. indexes are
+// 0-based, as are the ones taken by index_strict() and slice().
+//
+// postgres 14 lets types declare a subscripting handler, which would give us
+// `array[i]` and `array[i:j]` syntax on top of these functions. none of the
+// versions we build for (pg10-pg13) support that, so until we can target pg14
+// these functions are the only way to index into a SimpleArray
 #[pg_extern]
 fn index<'input>(state: SimpleArray<'input>, index: u32) -> Option<f64> {
     state.0.data.get(index as usize).cloned()
@@ -182,10 +484,138 @@ mod tests {
     #[pg_test]
     fn test_aggregate() {
         Spi::execute(|client| {
-            let value = client.select("SELECT index(array, 1) FROM (SELECT simple_array(i) array FROM generate_series(1, 100, 1) i) d", None, None)
+            let value = client.select("SELECT index(arr, 0) FROM (SELECT simple_array(i) arr FROM generate_series(1, 100, 1) i) d", None, None)
                 .first()
                 .get_one::<f64>();
             assert_eq!(value, Some(1.0));
         })
     }
+
+    #[pg_test]
+    fn test_aggregate_sql_matches_rust() {
+        let expected = crate::aggregate_utils::render_aggregates(&[
+            crate::SIMPLE_ARRAY_AGGREGATE,
+            crate::SIMPLE_ARRAY_SIZED_AGGREGATE,
+            crate::SIMPLE_ARRAY_SIZED_TRUNCATE_AGGREGATE,
+            crate::ordered_aggregate::SIMPLE_ARRAY_ORDERED_AGGREGATE,
+            crate::ordered_aggregate::SIMPLE_ARRAY_ORDERED_TIMESTAMPTZ_AGGREGATE,
+        ]);
+        assert_eq!(
+            include_str!("../sql/aggregate.sql"),
+            expected,
+            "sql/aggregate.sql is out of date with the aggregate! definitions"
+        );
+    }
+
+    #[pg_test]
+    fn test_moving_aggregate() {
+        Spi::execute(|client| {
+            let windows = client.select("SELECT (simple_array(i) OVER (ORDER BY i ROWS BETWEEN 2 PRECEDING AND CURRENT ROW))::text FROM generate_series(1, 5) i", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(windows, vec![
+                "[1.0]",
+                "[1.0, 2.0]",
+                "[1.0, 2.0, 3.0]",
+                "[2.0, 3.0, 4.0]",
+                "[3.0, 4.0, 5.0]",
+            ]);
+
+            let windows = client.select("SELECT (simple_array(i) OVER (ORDER BY i ROWS BETWEEN CURRENT ROW AND 1 FOLLOWING))::text FROM generate_series(1, 3) i", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(windows, vec!["[1.0, 2.0]", "[2.0, 3.0]", "[3.0]"]);
+        })
+    }
+
+    #[pg_test]
+    fn test_sized_aggregate() {
+        Spi::execute(|client| {
+            let (last, past_end) = client.select("SELECT index(arr, 9), index(arr, 10) FROM (SELECT simple_array(10, i) arr FROM generate_series(1, 10) i) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(last, Some(10.0));
+            assert_eq!(past_end, None);
+
+            let (last, past_end) = client.select("SELECT index(arr, 4), index(arr, 5) FROM (SELECT simple_array(5, i, true) arr FROM generate_series(1, 10) i) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(last, Some(5.0));
+            assert_eq!(past_end, None);
+        })
+    }
+
+    #[pg_test]
+    fn test_sized_aggregate_huge_size() {
+        Spi::execute(|client| {
+            let value = client.select("SELECT index(simple_array(2147483647, i), 2) FROM generate_series(1, 3) i", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(value, Some(3.0));
+        })
+    }
+
+    #[pg_test(error = "simple_array received more than the declared 5 values")]
+    fn test_sized_aggregate_overflow() {
+        Spi::get_one::<String>("SELECT simple_array(5, i)::text FROM generate_series(1, 10) i");
+    }
+
+    #[pg_test]
+    fn test_parallel_aggregate() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE parallel_values AS SELECT i::float8 AS value FROM generate_series(1, 100000) i", None, None);
+            client.update("SET parallel_setup_cost = 0", None, None);
+            client.update("SET parallel_tuple_cost = 0", None, None);
+            client.update("SET min_parallel_table_scan_size = 0", None, None);
+            client.update("SET max_parallel_workers_per_gather = 4", None, None);
+
+            let plan = client.select("EXPLAIN SELECT simple_array(value) FROM parallel_values", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Partial Aggregate"), "{}", plan);
+
+            let (last, past_end) = client.select("SELECT index(arr, 99999), index(arr, 100000) FROM (SELECT simple_array(value) arr FROM parallel_values) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert!(last.is_some());
+            assert_eq!(past_end, None);
+
+            // workers that don't see any rows send back NULL states
+            let value = client.select("SELECT index(arr, 0) FROM (SELECT simple_array(value) arr FROM parallel_values WHERE value = 1) d", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(value, Some(1.0));
+
+            let array = client.select("SELECT simple_array(value)::text FROM parallel_values WHERE value < 0", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(array, None);
+        })
+    }
+
+    #[pg_test]
+    fn test_input_round_trip() {
+        Spi::execute(|client| {
+            let value = client.select("SELECT index(' [1.0, 2.5e1,NaN, -inf ] '::SimpleArray, 1)", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(value, Some(25.0));
+
+            let text = client.select("SELECT '[1.0, 2.5, NaN, inf]'::SimpleArray::text", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[1.0, 2.5, NaN, inf]"));
+
+            let text = client.select("SELECT '[]'::SimpleArray::text", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[]"));
+        })
+    }
+
+    #[pg_test(error = "invalid input syntax for type simplearray: \"[1.0, x]\" at position 7: invalid number")]
+    fn test_input_error() {
+        Spi::get_one::<String>("SELECT '[1.0, x]'::SimpleArray::text");
+    }
 }
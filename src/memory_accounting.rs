@@ -0,0 +1,284 @@
+use std::{collections::VecDeque, mem::size_of, ops::Deref};
+
+use pgx::*;
+
+// accounting for the memory used by aggregate states. states that can grow
+// without bound keep their values in an AccountedVec or AccountedVecDeque,
+// which grow the underlying collection
+// itself so it knows how much memory the state will use before allocating it,
+// and raises an ERROR rather than let a single state grow past
+// fs_example.max_state_bytes. the limit applies to each state separately, so
+// many small groups, or several aggregates in one query, don't add up
+
+static MAX_STATE_BYTES: GucSetting<i32> = GucSetting::new(0);
+
+pub fn init() {
+    GucRegistry::define_int_guc(
+        "fs_example.max_state_bytes",
+        "The most memory, in bytes, that an aggregate's state may use.",
+        "Once an aggregate's state would grow past this many bytes an ERROR is raised. 0 disables the limit.",
+        &MAX_STATE_BYTES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+    );
+}
+
+// backends are single-threaded, so plain statics are fine
+static mut LIVE_BYTES: usize = 0;
+static mut PEAK_BYTES: usize = 0;
+
+/// A `Vec` whose memory counts towards fs_example.max_state_bytes. It only
+/// derefs immutably; everything that can grow it goes through the methods
+/// below, which check the limit first.
+pub struct AccountedVec<T> {
+    values: Vec<T>,
+    bytes: usize,
+}
+
+impl<T> AccountedVec<T> {
+    pub fn new() -> Self {
+        Self { values: Vec::new(), bytes: 0 }
+    }
+
+    // reserving space up front is only an optimization, so rather than erroring
+    // this reserves no more than the limit allows
+    pub fn with_capacity(capacity: usize) -> Self {
+        let capacity = match max_capacity::<T>() {
+            Some(max_capacity) => capacity.min(max_capacity),
+            None => capacity,
+        };
+        let mut vec = Self { values: Vec::with_capacity(capacity), bytes: 0 };
+        vec.record();
+        vec
+    }
+
+    pub fn from_vec(values: Vec<T>) -> Self {
+        if let Some(max_capacity) = max_capacity::<T>() {
+            if values.capacity() > max_capacity {
+                limit_exceeded()
+            }
+        }
+        let mut vec = Self { values, bytes: 0 };
+        vec.record();
+        vec
+    }
+
+    pub fn push(&mut self, value: T) {
+        self.reserve(1);
+        self.values.push(value);
+    }
+
+    pub fn extend_from_slice(&mut self, values: &[T])
+    where
+        T: Clone,
+    {
+        self.reserve(values.len());
+        self.values.extend_from_slice(values);
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        let (len, capacity) = (self.values.len(), self.values.capacity());
+        if let Some(capacity) = grown_capacity::<T>(len, capacity, additional) {
+            self.values.reserve_exact(capacity - len);
+            self.record();
+        }
+    }
+
+    fn record(&mut self) {
+        record_bytes(&mut self.bytes, self.values.capacity() * size_of::<T>());
+    }
+}
+
+impl<T> Default for AccountedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> Clone for AccountedVec<T> {
+    fn clone(&self) -> Self {
+        Self::from_vec(self.values.clone())
+    }
+}
+
+impl<T> Deref for AccountedVec<T> {
+    type Target = Vec<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl<T> Drop for AccountedVec<T> {
+    fn drop(&mut self) {
+        unsafe { LIVE_BYTES -= self.bytes }
+    }
+}
+
+/// A `VecDeque` whose memory counts towards fs_example.max_state_bytes, for
+/// states that remove values from the front as well as adding them to the back.
+pub struct AccountedVecDeque<T> {
+    values: VecDeque<T>,
+    bytes: usize,
+}
+
+impl<T> AccountedVecDeque<T> {
+    pub fn new() -> Self {
+        Self { values: VecDeque::new(), bytes: 0 }
+    }
+
+    pub fn push_back(&mut self, value: T) {
+        let (len, capacity) = (self.values.len(), self.values.capacity());
+        if let Some(capacity) = grown_capacity::<T>(len, capacity, 1) {
+            self.values.reserve_exact(capacity - len);
+            record_bytes(&mut self.bytes, self.values.capacity() * size_of::<T>());
+        }
+        self.values.push_back(value);
+    }
+
+    // the deque keeps its capacity, so this doesn't change what it uses
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.values.pop_front()
+    }
+}
+
+impl<T> Default for AccountedVecDeque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for AccountedVecDeque<T> {
+    type Target = VecDeque<T>;
+    fn deref(&self) -> &Self::Target {
+        &self.values
+    }
+}
+
+impl<T> Drop for AccountedVecDeque<T> {
+    fn drop(&mut self) {
+        unsafe { LIVE_BYTES -= self.bytes }
+    }
+}
+
+// the capacity to grow to so there's room for `additional` more values, or
+// None if there already is. grows geometrically, as Vec does, but never past
+// the limit
+fn grown_capacity<T>(len: usize, capacity: usize, additional: usize) -> Option<usize> {
+    let required = len.saturating_add(additional);
+    if required <= capacity {
+        return None;
+    }
+    let mut grown = required.max(capacity * 2).max(4);
+    if let Some(max_capacity) = max_capacity::<T>() {
+        if required > max_capacity {
+            limit_exceeded()
+        }
+        grown = grown.min(max_capacity);
+    }
+    Some(grown)
+}
+
+fn record_bytes(recorded: &mut usize, bytes: usize) {
+    unsafe {
+        LIVE_BYTES = LIVE_BYTES - *recorded + bytes;
+        PEAK_BYTES = PEAK_BYTES.max(bytes);
+    }
+    *recorded = bytes;
+}
+
+fn max_capacity<T>() -> Option<usize> {
+    match MAX_STATE_BYTES.get() {
+        limit if limit <= 0 => None,
+        limit => Some(limit as usize / size_of::<T>().max(1)),
+    }
+}
+
+fn limit_exceeded() -> ! {
+    error!(
+        "aggregate state would exceed fs_example.max_state_bytes ({} bytes)",
+        MAX_STATE_BYTES.get()
+    )
+}
+
+// debug functions to see how large aggregate states get: the memory used by all
+// the states that currently exist in this backend, and the most any one state
+// has used since the last reset. parallel workers keep their own counts
+
+#[pg_extern]
+fn aggregate_memory_usage() -> i64 {
+    unsafe { LIVE_BYTES as i64 }
+}
+
+#[pg_extern]
+fn aggregate_memory_peak() -> i64 {
+    unsafe { PEAK_BYTES as i64 }
+}
+
+#[pg_extern]
+fn reset_aggregate_memory_peak() {
+    unsafe { PEAK_BYTES = 0 }
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_aggregate_memory_usage() {
+        Spi::execute(|client| {
+            client.select("SELECT reset_aggregate_memory_peak()", None, None);
+            let usage = client.select("SELECT aggregate_memory_usage() FROM (SELECT simple_array(i) FROM generate_series(1, 1000) i) d", None, None)
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert!(usage >= 1000 * 8, "{}", usage);
+
+            let peak = client.select("SELECT aggregate_memory_peak()", None, None)
+                .first()
+                .get_one::<i64>()
+                .unwrap();
+            assert!(peak >= 1000 * 8, "{}", peak);
+
+            // the states are dropped along with the aggregate's memory context
+            let usage = client.select("SELECT aggregate_memory_usage()", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(usage, Some(0));
+        })
+    }
+
+    #[pg_test(error = "aggregate state would exceed fs_example.max_state_bytes (1024 bytes)")]
+    fn test_max_state_bytes() {
+        Spi::execute(|client| {
+            client.update("SET LOCAL fs_example.max_state_bytes = 1024", None, None);
+            client.select("SELECT simple_array(i) FROM generate_series(1, 1000) i", None, None);
+        })
+    }
+
+    #[pg_test]
+    fn test_max_state_bytes_per_state() {
+        Spi::execute(|client| {
+            client.update("SET LOCAL fs_example.max_state_bytes = 1024", None, None);
+
+            let groups = client.select("SELECT count(*) FROM (SELECT simple_array(i) FROM generate_series(1, 10000) i GROUP BY i % 1000) d", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(groups, Some(1000));
+
+            let (a, b) = client.select("SELECT index(simple_array(i), 99), index(simple_array(-i), 99) FROM generate_series(1, 100) i", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!((a, b), (Some(100.0), Some(-100.0)));
+        })
+    }
+
+    // the frame grows to every row, so the moving state goes over the limit
+    #[pg_test(error = "aggregate state would exceed fs_example.max_state_bytes (1024 bytes)")]
+    fn test_max_state_bytes_moving() {
+        Spi::execute(|client| {
+            client.update("SET LOCAL fs_example.max_state_bytes = 1024", None, None);
+            client.select("SELECT simple_array(i) OVER (ORDER BY i ROWS BETWEEN CURRENT ROW AND UNBOUNDED FOLLOWING) FROM generate_series(1, 1000) i", None, None);
+        })
+    }
+}
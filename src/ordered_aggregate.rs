@@ -0,0 +1,352 @@
+use std::{cmp::Ordering, convert::TryInto, mem::size_of};
+
+use pgx::*;
+
+use crate::{
+    aggregate_utils::{aggregate_mctx, in_aggregate_context},
+    memory_accounting::AccountedVec,
+    palloc::Internal,
+    stats::float_cmp,
+    SimpleArray,
+};
+
+use raw::TimestampWithTimeZone;
+
+// a variant of simple_array that orders its output by a key given alongside
+// each value, e.g. `simple_array_ordered(reading, time)`.
+// unlike `simple_array(value ORDER BY key)` this still works with parallel
+// aggregation: we keep (key, value) pairs in the state and only sort them in
+// the final function, so the result is the same regardless of the order the
+// workers' states are combined in. ties are broken by value, so that the
+// output is fully deterministic
+
+// the state is generic over the key so that each key type keeps its own
+// representation: timestamps are kept as their raw microseconds, which f64
+// couldn't represent exactly for all of them
+type OrderedState<K> = AccountedVec<(K, f64)>;
+
+trait OrderedKey: Copy {
+    fn compare(a: Self, b: Self) -> Ordering;
+    fn to_bytes(self) -> [u8; 8];
+    fn from_bytes(bytes: [u8; 8]) -> Self;
+}
+
+impl OrderedKey for f64 {
+    fn compare(a: Self, b: Self) -> Ordering {
+        float_cmp(a, b)
+    }
+
+    fn to_bytes(self) -> [u8; 8] {
+        self.to_ne_bytes()
+    }
+
+    fn from_bytes(bytes: [u8; 8]) -> Self {
+        f64::from_ne_bytes(bytes)
+    }
+}
+
+// a TimestampTz, whose infinities are i64::MIN and i64::MAX, so they sort
+// before and after every other timestamp
+impl OrderedKey for i64 {
+    fn compare(a: Self, b: Self) -> Ordering {
+        a.cmp(&b)
+    }
+
+    fn to_bytes(self) -> [u8; 8] {
+        self.to_ne_bytes()
+    }
+
+    fn from_bytes(bytes: [u8; 8]) -> Self {
+        i64::from_ne_bytes(bytes)
+    }
+}
+
+unsafe fn ordered_trans<K: OrderedKey>(
+    state: Option<Internal<OrderedState<K>>>,
+    value: f64,
+    key: K,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<K>>> {
+    in_aggregate_context(fcinfo, || {
+        let mut state = state.unwrap_or_else(|| Internal::new_owned(AccountedVec::new()));
+
+        state.push((key, value));
+
+        Some(state)
+    })
+}
+
+unsafe fn ordered_combine<K: OrderedKey>(
+    state1: Option<Internal<OrderedState<K>>>,
+    state2: Option<Internal<OrderedState<K>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<K>>> {
+    in_aggregate_context(fcinfo, || match (state1, state2) {
+        (None, None) => None,
+        (None, Some(state2)) => Some(Internal::new_owned((*state2).clone())),
+        (Some(state1), None) => Some(state1),
+        (Some(mut state1), Some(state2)) => {
+            state1.extend_from_slice(&state2);
+            Some(state1)
+        }
+    })
+}
+
+fn ordered_serialize<K: OrderedKey>(state: Internal<OrderedState<K>>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(state.len() * 2 * size_of::<f64>());
+    for (key, value) in state.iter() {
+        bytes.extend_from_slice(&key.to_bytes());
+        bytes.extend_from_slice(&value.to_ne_bytes());
+    }
+    bytes
+}
+
+unsafe fn ordered_deserialize<K: OrderedKey>(
+    bytes: Option<&[u8]>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<K>>> {
+    let bytes = bytes?;
+    if bytes.len() % (2 * size_of::<f64>()) != 0 {
+        error!("invalid serialized simple_array_ordered state of {} bytes", bytes.len())
+    }
+    in_aggregate_context(fcinfo, || {
+        let state: Vec<(K, f64)> = bytes
+            .chunks_exact(2 * size_of::<f64>())
+            .map(|pair| {
+                let (key, value) = pair.split_at(size_of::<f64>());
+                (
+                    K::from_bytes(key.try_into().unwrap()),
+                    f64::from_ne_bytes(value.try_into().unwrap()),
+                )
+            })
+            .collect();
+        Some(Internal::new_owned(AccountedVec::from_vec(state)))
+    })
+}
+
+// like simple_array_final this doesn't modify the state, so it sorts a copy
+fn ordered_final<K: OrderedKey>(
+    state: Option<Internal<OrderedState<K>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SimpleArray<'static>> {
+    if aggregate_mctx(fcinfo).is_none() {
+        error!("cannot call as non-aggregate")
+    }
+    let state = match state {
+        None => return None,
+        Some(state) => state,
+    };
+    let mut pairs = state.to_vec();
+    pairs.sort_by(|(key1, value1), (key2, value2)| match K::compare(*key1, *key2) {
+        Ordering::Equal => float_cmp(*value1, *value2),
+        ordering => ordering,
+    });
+    let values: Vec<f64> = pairs.into_iter().map(|(_, value)| value).collect();
+    SimpleArray::from_values(&values).into()
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_trans(
+    state: Option<Internal<OrderedState<f64>>>,
+    value: f64,
+    key: f64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<f64>>> {
+    unsafe { ordered_trans(state, value, key, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_combine(
+    state1: Option<Internal<OrderedState<f64>>>,
+    state2: Option<Internal<OrderedState<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<f64>>> {
+    unsafe { ordered_combine(state1, state2, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_serialize(state: Internal<OrderedState<f64>>) -> Vec<u8> {
+    ordered_serialize(state)
+}
+
+// not strict for the same reason as simple_array_deserialize
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_deserialize(
+    bytes: Option<&[u8]>,
+    _internal: Option<Internal<()>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<f64>>> {
+    unsafe { ordered_deserialize(bytes, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_final(
+    state: Option<Internal<OrderedState<f64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SimpleArray<'static>> {
+    ordered_final(state, fcinfo)
+}
+
+// the timestamptz overload, which keys by the raw TimestampTz
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_timestamptz_trans(
+    state: Option<Internal<OrderedState<i64>>>,
+    value: f64,
+    key: TimestampWithTimeZone,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<i64>>> {
+    unsafe { ordered_trans(state, value, key.0, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_timestamptz_combine(
+    state1: Option<Internal<OrderedState<i64>>>,
+    state2: Option<Internal<OrderedState<i64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<i64>>> {
+    unsafe { ordered_combine(state1, state2, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_timestamptz_serialize(state: Internal<OrderedState<i64>>) -> Vec<u8> {
+    ordered_serialize(state)
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_timestamptz_deserialize(
+    bytes: Option<&[u8]>,
+    _internal: Option<Internal<()>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<Internal<OrderedState<i64>>> {
+    unsafe { ordered_deserialize(bytes, fcinfo) }
+}
+
+#[pg_extern(parallel_safe)]
+fn simple_array_ordered_timestamptz_final(
+    state: Option<Internal<OrderedState<i64>>>,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> Option<SimpleArray<'static>> {
+    ordered_final(state, fcinfo)
+}
+
+aggregate! {
+    pub(crate) const SIMPLE_ARRAY_ORDERED_AGGREGATE =
+        simple_array_ordered(value: f64, key: f64) {
+        state: OrderedState<f64>,
+        sfunc: simple_array_ordered_trans,
+        finalfunc: simple_array_ordered_final -> SimpleArray<'static>,
+        combinefunc: simple_array_ordered_combine,
+        serialfunc: simple_array_ordered_serialize,
+        deserialfunc: simple_array_ordered_deserialize,
+    }
+}
+
+aggregate! {
+    pub(crate) const SIMPLE_ARRAY_ORDERED_TIMESTAMPTZ_AGGREGATE =
+        simple_array_ordered(value: f64, key: TimestampWithTimeZone) {
+        state: OrderedState<i64>,
+        sfunc: simple_array_ordered_timestamptz_trans,
+        finalfunc: simple_array_ordered_timestamptz_final -> SimpleArray<'static>,
+        combinefunc: simple_array_ordered_timestamptz_combine,
+        serialfunc: simple_array_ordered_timestamptz_serialize,
+        deserialfunc: simple_array_ordered_timestamptz_deserialize,
+    }
+}
+
+// pgx's TimestampWithTimeZone converts to a date and time, which loses the
+// infinities, so we take the argument as the raw TimestampTz instead. it has
+// the same name so that pgx's schema generator still declares the argument as
+// timestamp with time zone
+mod raw {
+    use pgx::*;
+
+    use crate::aggregate_utils::SqlType;
+
+    pub struct TimestampWithTimeZone(pub pg_sys::TimestampTz);
+
+    impl FromDatum for TimestampWithTimeZone {
+        #[inline]
+        unsafe fn from_datum(
+            datum: pg_sys::Datum,
+            is_null: bool,
+            _: pg_sys::Oid,
+        ) -> Option<TimestampWithTimeZone> {
+            if is_null {
+                return None
+            }
+            Some(TimestampWithTimeZone(datum as pg_sys::TimestampTz))
+        }
+    }
+
+    impl SqlType for TimestampWithTimeZone {
+        const NAME: &'static str = "timestamp with time zone";
+    }
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_ordered_aggregate() {
+        Spi::execute(|client| {
+            let text = client.select("SELECT simple_array_ordered(value, key)::text FROM (VALUES (3.0, 30.0), (1.0, 10.0), (2.5, 20.0), (2.0, 20.0)) v(value, key)", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[1.0, 2.0, 2.5, 3.0]"));
+        })
+    }
+
+    #[pg_test]
+    fn test_ordered_aggregate_timestamptz() {
+        Spi::execute(|client| {
+            let text = client.select("SELECT simple_array_ordered(value, time)::text FROM (VALUES (3.0, '2021-01-01 00:00:00.000002+00'::timestamptz), (1.0, '1999-12-31 23:00:00-05'), (2.0, '2021-01-01 00:00:00.000001+00')) v(value, time)", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[1.0, 2.0, 3.0]"));
+        })
+    }
+
+    #[pg_test]
+    fn test_ordered_aggregate_timestamptz_infinity() {
+        Spi::execute(|client| {
+            let text = client.select("SELECT simple_array_ordered(value, time)::text FROM (VALUES (3.0, 'infinity'::timestamptz), (1.0, '-infinity'), (2.0, '2021-01-01 00:00:00+00'), (1.5, '1900-01-01 00:00:00+00')) v(value, time)", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[1.0, 1.5, 2.0, 3.0]"));
+        })
+    }
+
+    #[pg_test]
+    fn test_ordered_aggregate_parallel() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE shuffled AS SELECT i::float8 AS key, -i::float8 AS value FROM generate_series(1, 100000) i ORDER BY random()", None, None);
+            client.update("SET parallel_setup_cost = 0", None, None);
+            client.update("SET parallel_tuple_cost = 0", None, None);
+            client.update("SET min_parallel_table_scan_size = 0", None, None);
+            client.update("SET max_parallel_workers_per_gather = 4", None, None);
+
+            let plan = client.select("EXPLAIN SELECT simple_array_ordered(value, key) FROM shuffled", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("Partial Aggregate"), "{}", plan);
+
+            let sorted = client.select("SELECT simple_array_ordered(value, key) = sort(simple_array(value), true) FROM shuffled", None, None)
+                .first()
+                .get_one::<bool>();
+            assert_eq!(sorted, Some(true));
+
+            // workers that don't see any rows send back NULL states
+            let text = client.select("SELECT simple_array_ordered(value, key)::text FROM shuffled WHERE key = 1", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text.as_deref(), Some("[-1.0]"));
+
+            let text = client.select("SELECT simple_array_ordered(value, key)::text FROM shuffled WHERE key < 0", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(text, None);
+        })
+    }
+}
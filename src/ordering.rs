@@ -0,0 +1,108 @@
+use std::cmp::Ordering;
+
+use pgx::*;
+
+use crate::{stats::float_cmp, SimpleArray};
+
+// comparison functions and the btree operator class, defined in
+// sql/ordering.sql. SimpleArrays are ordered lexicographically, comparing
+// elements the same way float8 does: NaN equals itself and sorts after every
+// other value, and -0.0 equals 0.0. an array that is a prefix of another sorts
+// first
+
+pub(crate) fn compare(a: &[f64], b: &[f64]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        match float_cmp(*a, *b) {
+            Ordering::Equal => continue,
+            ordering => return ordering,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_cmp<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> i32 {
+    match compare(a.0.data, b.0.data) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_eq<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    compare(a.0.data, b.0.data) == Ordering::Equal
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_ne<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    compare(a.0.data, b.0.data) != Ordering::Equal
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_lt<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    compare(a.0.data, b.0.data) == Ordering::Less
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_le<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    compare(a.0.data, b.0.data) != Ordering::Greater
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gt<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    compare(a.0.data, b.0.data) == Ordering::Greater
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_ge<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> bool {
+    compare(a.0.data, b.0.data) != Ordering::Less
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_comparisons() {
+        Spi::execute(|client| {
+            let (zeros, nans) = client.select("SELECT '[-0.0]'::SimpleArray = '[0.0]'::SimpleArray, '[NaN]'::SimpleArray = '[NaN]'::SimpleArray", None, None)
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(zeros, Some(true));
+            assert_eq!(nans, Some(true));
+
+            let (prefix, nan_last) = client.select("SELECT '[1.0]'::SimpleArray < '[1.0, 0.0]'::SimpleArray, '[NaN]'::SimpleArray > '[inf]'::SimpleArray", None, None)
+                .first()
+                .get_two::<bool, bool>();
+            assert_eq!(prefix, Some(true));
+            assert_eq!(nan_last, Some(true));
+        })
+    }
+
+    #[pg_test]
+    fn test_btree_opclass() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE sorted_arrays (a SimpleArray UNIQUE)", None, None);
+            client.update("INSERT INTO sorted_arrays VALUES ('[2.0]'), ('[NaN]'), ('[1.0, 5.0]'), ('[]'), ('[1.0]')", None, None);
+
+            let sorted = client.select("SELECT a::text FROM sorted_arrays ORDER BY a", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(sorted, vec!["[]", "[1.0]", "[1.0, 5.0]", "[2.0]", "[NaN]"]);
+
+            let distinct = client.select("SELECT count(DISTINCT a) FROM (VALUES ('[0.0]'::SimpleArray), ('[-0.0]'), ('[1.0]')) v(a)", None, None)
+                .first()
+                .get_one::<i64>();
+            assert_eq!(distinct, Some(2));
+        })
+    }
+
+    #[pg_test(error = "duplicate key value violates unique constraint \"unique_arrays_a_key\"")]
+    fn test_unique_constraint() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE unique_arrays (a SimpleArray UNIQUE)", None, None);
+            client.update("INSERT INTO unique_arrays VALUES ('[1.0, 0.0]'), ('[1.0, -0.0]')", None, None);
+        })
+    }
+}
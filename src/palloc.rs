@@ -1,64 +1,160 @@
+#[cfg(debug_assertions)]
+use std::{
+    any::{type_name, TypeId},
+    mem::align_of,
+};
 use std::{
     alloc::{GlobalAlloc, Layout},
     convert::TryInto,
-    ptr::NonNull,
+    ffi::c_void,
+    mem::size_of,
+    ptr::{self, NonNull},
 };
 
 use pgx::*;
 
+// all of the crate's allocations go through postgres. by default they're made
+// in TopMemoryContext, since std, pgx, and this crate keep some allocations in
+// statics and thread-locals, which would dangle if they were allocated in a
+// shorter-lived context. code that wants its allocations freed along with a
+// particular context, such as aggregate states, chooses it with
+// in_memory_context
+#[global_allocator]
+static ALLOCATOR: PallocAllocator = PallocAllocator;
+
 struct PallocAllocator;
 
+// the context chosen by the innermost in_memory_context, if any
+static mut ALLOCATION_CONTEXT: pg_sys::MemoryContext = ptr::null_mut();
+
+unsafe fn allocation_context() -> pg_sys::MemoryContext {
+    if ALLOCATION_CONTEXT.is_null() {
+        pg_sys::TopMemoryContext
+    } else {
+        ALLOCATION_CONTEXT
+    }
+}
+
+// std lazily allocates a handle for the current thread, e.g. the first time
+// something panics; make sure that happens in TopMemoryContext, rather than in
+// whichever context the first panic happens in
+pub fn init() {
+    let _ = std::thread::current();
+}
+
 /// There is an uncomfortable mismatch between rust's memory allocation and
 /// postgres's; rust tries to clean memory by using stack-based destructors,
 /// while postgres does so using arenas. The issue we encounter is that postgres
 /// implements exception-handling using setjmp/longjmp, which will can jump over
-/// stack frames containing rust destructors. To avoid needing to register a
-/// setjmp handler at every call to a postgres function, we use postgres's
-/// MemoryContexts to manage memory, even though this is not strictly speaking
-/// safe. Though it is tempting to try to get more control over which
-/// MemoryContext we allocate in, there doesn't seem to be way to do so that is
-/// safe in the context of postgres exceptions and doesn't incur the cost of
-/// setjmp
+/// stack frames containing rust destructors. pgx catches postgres ERRORs and
+/// turns them into panics that unwind through our stack frames, so values that
+/// live on the stack are dropped as normal, and values whose lifetime is tied
+/// to a context get freed along with that context.
+///
+/// The allocator must not unwind, so we ask postgres to return NULL instead of
+/// raising an ERROR when it's out of memory, leaving it to rust's allocation
+/// failure handling; this also lifts palloc's 1GB limit on allocation sizes.
+/// There's no such variant of repalloc before postgres 16, so reallocating uses
+/// the default implementation: a fresh allocation, a copy, and a free.
+///
+/// palloc only guarantees MAXALIGN alignment, so for layouts that need more
+/// than that we over-allocate, align the pointer ourselves, and store the
+/// pointer palloc returned in the word just before the one we hand out
 unsafe impl GlobalAlloc for PallocAllocator {
-    //FIXME allow for switching the memory context allocated in
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        pg_sys::MemoryContextAlloc(
-            pg_sys::CurrentMemoryContext,
-            layout.size().try_into().unwrap()
-        )  as *mut _
+        allocate(layout, 0)
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        pg_sys::pfree(ptr as *mut _)
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if layout.align() <= MAX_ALIGN {
+            return pg_sys::pfree(ptr as *mut _)
+        }
+        pg_sys::pfree(*(ptr as *mut *mut u8).sub(1) as *mut _)
     }
 
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        pg_sys::MemoryContextAllocZero(
-            pg_sys::CurrentMemoryContext,
-            layout.size().try_into().unwrap()
-        ) as *mut _
+        allocate(layout, pg_sys::MCXT_ALLOC_ZERO)
     }
+}
 
-    unsafe fn realloc(&self, ptr: *mut u8, _layout: Layout, new_size: usize) -> *mut u8 {
-        pg_sys::repalloc(ptr as *mut _, new_size.try_into().unwrap()) as *mut _
+unsafe fn allocate(layout: Layout, flags: u32) -> *mut u8 {
+    let flags = (flags | pg_sys::MCXT_ALLOC_HUGE | pg_sys::MCXT_ALLOC_NO_OOM) as i32;
+    if layout.align() <= MAX_ALIGN {
+        return pg_sys::MemoryContextAllocExtended(
+            allocation_context(),
+            layout.size().try_into().unwrap(),
+            flags,
+        ) as *mut _
     }
+    let ptr = pg_sys::MemoryContextAllocExtended(
+        allocation_context(),
+        (layout.size() + layout.align()).try_into().unwrap(),
+        flags,
+    ) as *mut u8;
+    if ptr.is_null() {
+        return ptr
+    }
+    align_allocation(ptr, layout.align())
 }
 
+const MAX_ALIGN: usize = pg_sys::MAXIMUM_ALIGNOF as usize;
+
+// `ptr` is a MAXALIGNed allocation with `align` extra bytes. since `align` is a
+// power of two larger than MAXALIGN, there's always at least a MAXALIGN, and
+// therefore a pointer's worth, of space before the aligned address in which to
+// store the original pointer
+unsafe fn align_allocation(ptr: *mut u8, align: usize) -> *mut u8 {
+    let aligned = ((ptr as usize + align) & !(align - 1)) as *mut u8;
+    *(aligned as *mut *mut u8).sub(1) = ptr;
+    aligned
+}
+
+// runs `f` with `mctx` as the CurrentMemoryContext, and as the context rust
+// allocations are made in. the previous contexts are restored however `f`
+// exits, see MemoryContextGuard
 pub unsafe fn in_memory_context<T, F: FnOnce() -> T>(
     mctx: pg_sys::MemoryContext,
     f: F
 ) -> T {
-    let prev_ctx = pg_sys::CurrentMemoryContext;
-    pg_sys::CurrentMemoryContext = mctx;
-    let t = f();
-    pg_sys::CurrentMemoryContext = prev_ctx;
-    t
+    let _guard = MemoryContextGuard::switch_to(mctx);
+    f()
+}
+
+/// Switches CurrentMemoryContext, along with the context rust allocations are
+/// made in, for as long as the guard is alive, restoring the previous contexts
+/// when it's dropped. Since we build with
+/// `panic = "unwind"` this happens on panics as well as normal returns, and
+/// because pgx turns postgres ERRORs raised by the functions it wraps into
+/// panics, which it only re-raises as ERRORs once they've unwound to the
+/// function boundary, it also happens when a postgres function we call errors.
+pub struct MemoryContextGuard {
+    prev_ctx: pg_sys::MemoryContext,
+    prev_allocation_ctx: pg_sys::MemoryContext,
+}
+
+impl MemoryContextGuard {
+    pub unsafe fn switch_to(mctx: pg_sys::MemoryContext) -> Self {
+        let prev_ctx = pg_sys::CurrentMemoryContext;
+        let prev_allocation_ctx = ALLOCATION_CONTEXT;
+        pg_sys::CurrentMemoryContext = mctx;
+        ALLOCATION_CONTEXT = mctx;
+        Self { prev_ctx, prev_allocation_ctx }
+    }
+}
+
+impl Drop for MemoryContextGuard {
+    fn drop(&mut self) {
+        unsafe {
+            pg_sys::CurrentMemoryContext = self.prev_ctx;
+            ALLOCATION_CONTEXT = self.prev_allocation_ctx;
+        }
+    }
 }
 
 
 pub struct Internal<T>(pub NonNull<T>);
 
-impl<T> FromDatum for Internal<T> {
+impl<T: 'static> FromDatum for Internal<T> {
     #[inline]
     unsafe fn from_datum(
         datum: pg_sys::Datum,
@@ -68,9 +164,13 @@ impl<T> FromDatum for Internal<T> {
         if is_null {
             return None
         }
-        let nn = NonNull::new(datum as *mut T).unwrap_or_else(||
-                panic!("Internal-type Datum flagged not null but its datum is zero"));
-        Some(Internal(nn))
+        // postgres passes some dummy `internal` arguments, such as the second
+        // argument to a deserialize function, as a non-null zero, so we treat
+        // those as NULL too
+        let ptr = NonNull::new(datum as *mut T)?;
+        #[cfg(debug_assertions)]
+        check_type(ptr);
+        Some(Internal(ptr))
     }
 }
 
@@ -84,9 +184,91 @@ impl<T> IntoDatum for Internal<T> {
     }
 }
 
-impl<T> From<T> for Internal<T> {
+impl<T: 'static> From<T> for Internal<T> {
     fn from(t: T) -> Self {
-        Self(Box::leak(Box::new(t)).into())
+        let tagged = Box::leak(Box::new(Tagged::new(t)));
+        Self((&mut tagged.value).into())
+    }
+}
+
+impl<T: 'static> Internal<T> {
+    /// Like `from`, which leaks its value so its `Drop` impl never runs, except
+    /// that this registers a reset callback on CurrentMemoryContext that drops
+    /// the value when that context is reset or deleted. Called from inside
+    /// `in_aggregate_context` this ties the value's lifetime to the aggregate's.
+    /// The callback runs exactly once, and nothing else may drop the value.
+    pub unsafe fn new_owned(t: T) -> Self {
+        let tagged = Box::into_raw(Box::new(Tagged::new(t)));
+
+        // the callback struct must live as long as the context, so allocate it
+        // in there too
+        let callback = pg_sys::MemoryContextAlloc(
+            pg_sys::CurrentMemoryContext,
+            size_of::<pg_sys::MemoryContextCallback>().try_into().unwrap()
+        ) as *mut pg_sys::MemoryContextCallback;
+        (*callback).func = Some(drop_internal::<T>);
+        (*callback).arg = tagged as *mut c_void;
+        pg_sys::MemoryContextRegisterResetCallback(pg_sys::CurrentMemoryContext, callback);
+
+        Self(NonNull::new_unchecked(&mut (*tagged).value))
+    }
+}
+
+// runs before the context frees its memory, so the value, and anything it
+// allocated in the context, is still valid here
+unsafe extern "C" fn drop_internal<T>(arg: *mut c_void) {
+    drop(Box::from_raw(arg as *mut Tagged<T>))
+}
+
+// the values we store behind an Internal. `internal` datums are untyped, so
+// calling the wrong function on one would reinterpret it as the wrong type; to
+// catch that, in debug builds each value is preceded by a header recording its
+// type, which from_datum checks
+#[repr(C)]
+struct Tagged<T> {
+    #[cfg(debug_assertions)]
+    tag: TypeTag,
+    value: T,
+}
+
+#[cfg(debug_assertions)]
+#[repr(C)]
+struct TypeTag {
+    magic: u64,
+    type_id: TypeId,
+}
+
+#[cfg(debug_assertions)]
+const TYPE_TAG_MAGIC: u64 = 0x5349_4D50_4C45_4152;
+
+impl<T: 'static> Tagged<T> {
+    fn new(value: T) -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            tag: TypeTag {
+                magic: TYPE_TAG_MAGIC,
+                type_id: TypeId::of::<T>(),
+            },
+            value,
+        }
+    }
+}
+
+// every Internal was created by us, so a missing header means we were passed
+// something else, such as another aggregate's state. pointers that postgres
+// owns don't have a header, and must be taken as a raw::Internal instead
+#[cfg(debug_assertions)]
+unsafe fn check_type<T: 'static>(ptr: NonNull<T>) {
+    // with repr(C) the value is at the first offset after the tag that's
+    // suitably aligned for it
+    let align = align_of::<T>();
+    let value_offset = (size_of::<TypeTag>() + align - 1) / align * align;
+    let tag = &*((ptr.as_ptr() as *const u8).sub(value_offset) as *const TypeTag);
+    if tag.magic != TYPE_TAG_MAGIC || tag.type_id != TypeId::of::<T>() {
+        error!(
+            "internal datum does not contain a value of type {}",
+            type_name::<T>()
+        )
     }
 }
 
@@ -102,3 +284,213 @@ impl<T> std::ops::DerefMut for Internal<T> {
         unsafe { self.0.as_mut() }
     }
 }
+
+/// `internal` pointers to memory that postgres owns, such as the StringInfo
+/// passed to a receive function, or the arguments to index support functions.
+/// These don't carry our type tag, so their type is taken on trust. The type
+/// shares its name with the tagged `Internal` because pgx's schema generator
+/// goes by the name when mapping rust types to SQL ones.
+pub mod raw {
+    use std::ptr::NonNull;
+
+    use pgx::*;
+
+    pub struct Internal<T>(pub NonNull<T>);
+
+    impl<T> FromDatum for Internal<T> {
+        #[inline]
+        unsafe fn from_datum(
+            datum: pg_sys::Datum,
+            is_null: bool,
+            _: pg_sys::Oid,
+        ) -> Option<Internal<T>> {
+            if is_null {
+                return None
+            }
+            Some(Internal(NonNull::new(datum as *mut T)?))
+        }
+    }
+
+    impl<T> IntoDatum for Internal<T> {
+        fn into_datum(self) -> Option<pg_sys::Datum> {
+            Some(self.0.as_ptr() as pg_sys::Datum)
+        }
+
+        fn type_oid() -> pg_sys::Oid {
+            pg_sys::INTERNALOID
+        }
+    }
+
+    impl<T> std::ops::Deref for Internal<T> {
+        type Target = T;
+        fn deref(&self) -> &Self::Target {
+            unsafe { self.0.as_ref() }
+        }
+    }
+
+    impl<T> std::ops::DerefMut for Internal<T> {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            unsafe { self.0.as_mut() }
+        }
+    }
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    use crate::{memory_accounting::AccountedVec, palloc::Internal};
+
+    #[pg_test]
+    fn test_context_restored_after_panic() {
+        unsafe {
+            let prev_ctx = pg_sys::CurrentMemoryContext;
+            let target = pg_sys::TopTransactionContext;
+            assert_ne!(prev_ctx, target);
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                crate::palloc::in_memory_context(target, || {
+                    assert_eq!(pg_sys::CurrentMemoryContext, target);
+                    panic!("forced panic")
+                })
+            }));
+            assert!(result.is_err());
+            assert_eq!(pg_sys::CurrentMemoryContext, prev_ctx);
+        }
+    }
+
+    // a trans function that panics, or raises an ERROR, inside
+    // in_aggregate_context, catches it, and checks that it's back in the
+    // context it was called in
+    #[pg_extern]
+    fn test_interrupted_trans(
+        state: Option<Internal<AccountedVec<f64>>>,
+        raise_error: bool,
+        fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Option<Internal<AccountedVec<f64>>> {
+        unsafe {
+            let prev_ctx = pg_sys::CurrentMemoryContext;
+            let mut interrupted_ctx = std::ptr::null_mut();
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                crate::aggregate_utils::in_aggregate_context(fcinfo, || {
+                    interrupted_ctx = pg_sys::CurrentMemoryContext;
+                    if raise_error {
+                        error!("forced error")
+                    }
+                    panic!("forced panic")
+                })
+            }));
+            assert!(result.is_err());
+            assert_eq!(Some(interrupted_ctx), crate::aggregate_utils::aggregate_mctx(fcinfo));
+            assert_ne!(interrupted_ctx, prev_ctx);
+            assert_eq!(pg_sys::CurrentMemoryContext, prev_ctx);
+        }
+        state
+    }
+
+    #[pg_test]
+    fn test_context_restored_in_aggregate() {
+        Spi::execute(|client| {
+            client.update("CREATE AGGREGATE test_interrupted(boolean) (stype = internal, sfunc = test_interrupted_trans, finalfunc = simple_array_final)", None, None);
+
+            let panicked = client.select("SELECT test_interrupted(false)::text FROM generate_series(1, 3)", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(panicked, None);
+
+            let errored = client.select("SELECT test_interrupted(true)::text FROM generate_series(1, 3)", None, None)
+                .first()
+                .get_one::<String>();
+            assert_eq!(errored, None);
+        })
+    }
+
+    #[pg_test]
+    fn test_owned_internal_dropped_on_reset() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct CountsDrops;
+        impl Drop for CountsDrops {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let context = PgMemoryContexts::new("test_owned_internal");
+        unsafe {
+            crate::palloc::in_memory_context(context.value(), || {
+                let _state = Internal::new_owned(CountsDrops);
+            });
+            assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+
+            pg_sys::MemoryContextReset(context.value());
+            assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        }
+        // resetting the context unregisters the callback, so deleting the
+        // context shouldn't drop the value again
+        drop(context);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+    }
+
+    #[pg_test]
+    fn test_internal_type_tag() {
+        let datum = Internal::from(1u64).into_datum().unwrap();
+        let value = unsafe { Internal::<u64>::from_datum(datum, false, pg_sys::INTERNALOID) };
+        assert_eq!(value.map(|value| *value), Some(1));
+    }
+
+    #[cfg(debug_assertions)]
+    #[pg_test(error = "internal datum does not contain a value of type i64")]
+    fn test_internal_type_mismatch() {
+        let datum = Internal::from(1u64).into_datum().unwrap();
+        unsafe { Internal::<i64>::from_datum(datum, false, pg_sys::INTERNALOID) };
+    }
+
+    #[cfg(debug_assertions)]
+    #[pg_test(error = "internal datum does not contain a value of type u64")]
+    fn test_internal_untagged() {
+        unsafe {
+            let buffer = pg_sys::palloc0(64) as *mut u8;
+            let datum = buffer.add(32) as pg_sys::Datum;
+            Internal::<u64>::from_datum(datum, false, pg_sys::INTERNALOID);
+        }
+    }
+
+    #[pg_test]
+    fn test_allocation_context() {
+        unsafe {
+            let boxed = Box::new(1u64);
+            assert!(pg_sys::MemoryContextContains(
+                pg_sys::TopMemoryContext,
+                &*boxed as *const u64 as *mut _,
+            ));
+
+            let context = PgMemoryContexts::new("test_allocation_context");
+            crate::palloc::in_memory_context(context.value(), || {
+                let boxed = Box::new(1u64);
+                assert!(pg_sys::MemoryContextContains(
+                    context.value(),
+                    &*boxed as *const u64 as *mut _,
+                ));
+            });
+        }
+    }
+
+    #[pg_test]
+    fn test_over_aligned_allocations() {
+        #[repr(align(64))]
+        struct Aligned(u64);
+
+        let boxed = Box::new(Aligned(1));
+        assert_eq!(&*boxed as *const Aligned as usize % 64, 0);
+
+        let mut values = vec![];
+        for i in 0..100 {
+            values.push(Aligned(i));
+            assert_eq!(values.as_ptr() as usize % 64, 0);
+        }
+        assert!(values.iter().enumerate().all(|(i, value)| value.0 == i as u64));
+    }
+}
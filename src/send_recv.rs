@@ -0,0 +1,131 @@
+use std::{convert::TryInto, mem::size_of, slice};
+
+use pgx::*;
+
+use crate::{palloc::raw::Internal, SimpleArray};
+
+// binary send/receive functions. the wire format is `len` followed by `len`
+// f64s, all in network byte order, the same as float8send. these are attached
+// to the type in sql/send_recv.sql
+#[pg_extern]
+fn simplearray_send<'input>(array: SimpleArray<'input>) -> Vec<u8> {
+    let data = array.0.data;
+    let mut buffer = Vec::with_capacity(size_of::<u32>() + data.len() * size_of::<f64>());
+    buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    for value in data {
+        buffer.extend_from_slice(&value.to_bits().to_be_bytes());
+    }
+    buffer
+}
+
+#[pg_extern]
+fn simplearray_recv(
+    mut buffer: Internal<pg_sys::StringInfoData>,
+    _typoid: pg_sys::Oid,
+    _typmod: i32,
+) -> SimpleArray<'static> {
+    let buffer = &mut *buffer;
+    let bytes = unsafe {
+        let remaining = (buffer.len - buffer.cursor) as usize;
+        slice::from_raw_parts(buffer.data.offset(buffer.cursor as isize) as *const u8, remaining)
+    };
+
+    if bytes.len() < size_of::<u32>() {
+        error!("invalid binary SimpleArray: missing length")
+    }
+    let (len, data) = bytes.split_at(size_of::<u32>());
+    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+    // validate the length against what we actually received before
+    // allocating anything based on it
+    let data_len = match len.checked_mul(size_of::<f64>()) {
+        Some(data_len) if data_len <= data.len() => data_len,
+        _ => error!(
+            "invalid binary SimpleArray: length {} but only {} bytes of data",
+            len,
+            data.len()
+        ),
+    };
+
+    let values: Vec<f64> = data[..data_len]
+        .chunks_exact(size_of::<f64>())
+        .map(|value| f64::from_bits(u64::from_be_bytes(value.try_into().unwrap())))
+        .collect();
+    // postgres will complain if we leave any of the message unread
+    buffer.cursor += (size_of::<u32>() + data_len) as i32;
+
+    SimpleArray::from_values(&values)
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    // COPY to or from a server-side file needs an absolute path, so we put
+    // the files in the data directory
+    fn binary_copy(table: &str, direction: &str, file: &str) -> String {
+        format!(
+            "DO $$ BEGIN EXECUTE format('COPY {} {} %L (FORMAT binary)', current_setting('data_directory') || '/{}'); END $$",
+            table, direction, file
+        )
+    }
+
+    #[pg_test]
+    fn test_send() {
+        Spi::execute(|client| {
+            let bytes = client.select("SELECT simplearray_send('[1.0, -2.0]'::SimpleArray)", None, None)
+                .first()
+                .get_one::<Vec<u8>>();
+            assert_eq!(bytes, Some(vec![
+                0, 0, 0, 2,
+                0x3f, 0xf0, 0, 0, 0, 0, 0, 0,
+                0xc0, 0, 0, 0, 0, 0, 0, 0,
+            ]));
+
+            let registered = client.select("SELECT typsend = 'simplearray_send'::regproc AND typreceive = 'simplearray_recv'::regproc FROM pg_type WHERE typname = 'simplearray'", None, None)
+                .first()
+                .get_one::<bool>();
+            assert_eq!(registered, Some(true));
+        })
+    }
+
+    // binary COPY goes through simplearray_send on the way out and
+    // simplearray_recv on the way back in
+    #[pg_test]
+    fn test_binary_copy_round_trip() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE copy_out (value SimpleArray)", None, None);
+            client.update("INSERT INTO copy_out VALUES ('[1.0, -2.5, NaN, inf]'), ('[]')", None, None);
+            client.update(&binary_copy("copy_out", "TO", "simplearray_round_trip.bin"), None, None);
+            client.update("CREATE TABLE copy_in (value SimpleArray)", None, None);
+            client.update(&binary_copy("copy_in", "FROM", "simplearray_round_trip.bin"), None, None);
+
+            let values = client.select("SELECT value::text FROM copy_in", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(values, vec!["[1.0, -2.5, NaN, inf]", "[]"]);
+        })
+    }
+
+    // to feed simplearray_recv malformed input we binary COPY out a bytea
+    // column, whose field contents are the raw bytes, and COPY it back in as
+    // a SimpleArray column
+    #[pg_test(error = "invalid binary SimpleArray: length 2 but only 8 bytes of data")]
+    fn test_recv_truncated() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE copy_raw AS SELECT '\\x000000023ff0000000000000'::bytea AS value", None, None);
+            client.update(&binary_copy("copy_raw", "TO", "simplearray_truncated.bin"), None, None);
+            client.update("CREATE TABLE copy_in (value SimpleArray)", None, None);
+            client.update(&binary_copy("copy_in", "FROM", "simplearray_truncated.bin"), None, None);
+        })
+    }
+
+    #[pg_test(error = "invalid binary SimpleArray: length 4294967295 but only 0 bytes of data")]
+    fn test_recv_oversized_length() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE copy_raw AS SELECT '\\xffffffff'::bytea AS value", None, None);
+            client.update(&binary_copy("copy_raw", "TO", "simplearray_oversized.bin"), None, None);
+            client.update("CREATE TABLE copy_in (value SimpleArray)", None, None);
+            client.update(&binary_copy("copy_in", "FROM", "simplearray_oversized.bin"), None, None);
+        })
+    }
+}
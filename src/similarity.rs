@@ -0,0 +1,427 @@
+use std::{cmp::Ordering, mem::size_of, ptr::NonNull};
+
+use pgx::*;
+
+use crate::{ordering::compare, palloc::raw::Internal, stats::float_cmp, SimpleArray};
+
+// vector similarity functions, treating each SimpleArray as a vector. the
+// operators are defined in sql/similarity.sql; as with other vector extensions
+// `<#>` is the _negative_ inner product, so that for all three operators
+// smaller means more similar. `<->` has index support through the GiST
+// operator class at the end of this file
+
+#[pg_extern(immutable, parallel_safe)]
+fn dot<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> f64 {
+    let (a, b) = vectors("dot", a, b);
+    sum_pairs(a, b, |a, b| a * b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_negative_dot<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> f64 {
+    -dot(a, b)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn l2_distance<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> f64 {
+    let (a, b) = vectors("l2_distance", a, b);
+    sum_pairs(a, b, |a, b| (a - b) * (a - b)).sqrt()
+}
+
+// the distance between two vectors is NaN if either of them is all zeros
+#[pg_extern(immutable, parallel_safe)]
+fn cosine_distance<'a, 'b>(a: SimpleArray<'a>, b: SimpleArray<'b>) -> f64 {
+    let (a, b) = vectors("cosine_distance", a, b);
+    let dot = sum_pairs(a, b, |a, b| a * b);
+    let a_norm = sum_pairs(a, a, |a, _| a * a).sqrt();
+    let b_norm = sum_pairs(b, b, |b, _| b * b).sqrt();
+    let similarity = dot / (a_norm * b_norm);
+    // clamping would turn NaN into -1.0, so return it before we do
+    if similarity.is_nan() {
+        return f64::NAN;
+    }
+    // rounding can push the similarity slightly outside of [-1, 1]
+    1.0 - similarity.max(-1.0).min(1.0)
+}
+
+fn vectors<'a, 'b>(
+    function: &str,
+    a: SimpleArray<'a>,
+    b: SimpleArray<'b>,
+) -> (&'a [f64], &'b [f64]) {
+    let (a, b) = (a.0.data, b.0.data);
+    if a.len() != b.len() {
+        error!(
+            "{} requires SimpleArrays of the same length, got {} and {}",
+            function,
+            a.len(),
+            b.len()
+        )
+    }
+    (a, b)
+}
+
+// sums f(a[i], b[i]) using several independent accumulators. float addition
+// isn't associative, so with a single accumulator the compiler must add the
+// values one at a time, while this lets it use SIMD
+fn sum_pairs(a: &[f64], b: &[f64], f: impl Fn(f64, f64) -> f64) -> f64 {
+    const LANES: usize = 8;
+    let mut sums = [0.0; LANES];
+    let (a_chunks, b_chunks) = (a.chunks_exact(LANES), b.chunks_exact(LANES));
+    let (a_rest, b_rest) = (a_chunks.remainder(), b_chunks.remainder());
+    for (a, b) in a_chunks.zip(b_chunks) {
+        for ((sum, a), b) in sums.iter_mut().zip(a).zip(b) {
+            *sum += f(*a, *b);
+        }
+    }
+
+    let mut sum: f64 = sums.iter().sum();
+    for (a, b) in a_rest.iter().zip(b_rest) {
+        sum += f(*a, *b);
+    }
+    sum
+}
+
+// a GiST operator class that lets `ORDER BY col <-> query LIMIT k` use an
+// index. the index is an R-tree: each key is the bounding box of the vectors
+// below it, stored as a SimpleArray of the lower bounds followed by the upper
+// bounds, and the distance from the query to a box is a lower bound on its
+// distance to anything inside it. every leaf's box is a single vector, for
+// which that distance is exactly l2_distance, so the results are exact.
+// this is not an approximate nearest-neighbor index: bounding boxes stop
+// pruning much of anything beyond a few dozen dimensions, and keys take 16
+// bytes per dimension, so it's only meant for low-dimensional vectors
+const L2_DISTANCE_STRATEGY: i16 = 1;
+
+// an index tuple has to fit on an 8kB page, less the page and tuple headers.
+// with 16 bytes per dimension that leaves room for a bit over 500 dimensions,
+// so we reject anything wider up front instead of failing on the tuple size
+const MAX_DIMENSIONS: usize = 500;
+
+// the first entry to split in picksplit's GistEntryVector
+const FIRST_OFFSET_NUMBER: usize = 1;
+
+// the opclass only has an ordering operator, so this is never called
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_consistent<'input>(
+    _entry: Internal<pg_sys::GISTENTRY>,
+    _query: SimpleArray<'input>,
+    strategy: i16,
+    _subtype: pg_sys::Oid,
+    _recheck: Internal<bool>,
+) -> bool {
+    error!("unrecognized strategy number: {}", strategy)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_union(
+    entries: Internal<pg_sys::GistEntryVector>,
+    mut size: Internal<i32>,
+) -> SimpleArray<'static> {
+    let keys = unsafe { entry_keys(&entries, 0) };
+    let union = SimpleArray::from_values(&union(keys.iter().copied()));
+    *size = unsafe { varsize_any(union.0.header as *const u32 as *const pg_sys::varlena) as i32 };
+    union
+}
+
+// leaf entries are vectors, which we store as the box containing just them
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_compress(entry: Internal<pg_sys::GISTENTRY>) -> Internal<pg_sys::GISTENTRY> {
+    if !entry.leafkey {
+        return entry;
+    }
+    let vector = unsafe { key(entry.key) };
+    if vector.len() > MAX_DIMENSIONS {
+        error!(
+            "simple_array_gist_ops supports SimpleArrays of at most {} elements, got {}",
+            MAX_DIMENSIONS,
+            vector.len()
+        )
+    }
+    let key = SimpleArray::from_values(&[vector, vector].concat());
+    unsafe {
+        let compressed =
+            pg_sys::palloc(size_of::<pg_sys::GISTENTRY>()) as *mut pg_sys::GISTENTRY;
+        *compressed = pg_sys::GISTENTRY {
+            key: key.into_datum().unwrap(),
+            rel: entry.rel,
+            page: entry.page,
+            offset: entry.offset,
+            leafkey: false,
+        };
+        Internal(NonNull::new_unchecked(compressed))
+    }
+}
+
+// postgres 10 requires a decompress function, even though ours does nothing
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_decompress(
+    entry: Internal<pg_sys::GISTENTRY>,
+) -> Internal<pg_sys::GISTENTRY> {
+    entry
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_penalty(
+    original: Internal<pg_sys::GISTENTRY>,
+    new: Internal<pg_sys::GISTENTRY>,
+    mut penalty: Internal<f32>,
+) -> Internal<f32> {
+    let (original, new) = unsafe { (key(original.key), key(new.key)) };
+    let union = union([original, new].iter().copied());
+    *penalty = (margin(&union) - margin(original)) as f32;
+    penalty
+}
+
+// splits along the dimension the entries are most spread out in, putting the
+// half with the smaller centers on the left
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_picksplit(
+    entries: Internal<pg_sys::GistEntryVector>,
+    mut split: Internal<pg_sys::GIST_SPLITVEC>,
+) -> Internal<pg_sys::GIST_SPLITVEC> {
+    let keys = unsafe { entry_keys(&entries, FIRST_OFFSET_NUMBER) };
+    let bounding_box = union(keys.iter().copied());
+    let (lower, upper) = bounds(&bounding_box);
+    let spread = |dim: usize| match upper[dim] - lower[dim] {
+        spread if spread.is_nan() => f64::NEG_INFINITY,
+        spread => spread,
+    };
+    let dims = lower.len();
+    let dim = (0..dims)
+        .max_by(|a, b| float_cmp(spread(*a), spread(*b)))
+        .unwrap_or(0);
+    let center = |key: &[f64]| match dims {
+        0 => 0.0,
+        _ => (key[dim] + key[dims + dim]) / 2.0,
+    };
+
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|a, b| float_cmp(center(keys[*a]), center(keys[*b])));
+    let (left, right) = order.split_at(order.len() / 2);
+
+    unsafe {
+        split.spl_left = offsets(left);
+        split.spl_nleft = left.len() as i32;
+        split.spl_ldatum = SimpleArray::from_values(&union(left.iter().map(|i| keys[*i])))
+            .into_datum()
+            .unwrap();
+        split.spl_right = offsets(right);
+        split.spl_nright = right.len() as i32;
+        split.spl_rdatum = SimpleArray::from_values(&union(right.iter().map(|i| keys[*i])))
+            .into_datum()
+            .unwrap();
+    }
+    // we don't take the existing unions into account
+    split.spl_ldatum_exists = false;
+    split.spl_rdatum_exists = false;
+    split
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_same<'a, 'b>(
+    a: SimpleArray<'a>,
+    b: SimpleArray<'b>,
+    mut same: Internal<bool>,
+) -> Internal<bool> {
+    *same = compare(a.0.data, b.0.data) == Ordering::Equal;
+    same
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn simple_array_gist_distance<'input>(
+    entry: Internal<pg_sys::GISTENTRY>,
+    query: SimpleArray<'input>,
+    strategy: i16,
+    _subtype: pg_sys::Oid,
+    mut recheck: Internal<bool>,
+) -> f64 {
+    if strategy != L2_DISTANCE_STRATEGY {
+        error!("unrecognized strategy number: {}", strategy)
+    }
+    *recheck = false;
+
+    let (lower, upper) = bounds(unsafe { key(entry.key) });
+    let query = query.0.data;
+    if lower.len() != query.len() {
+        error!(
+            "l2_distance requires SimpleArrays of the same length, got {} and {}",
+            lower.len(),
+            query.len()
+        )
+    }
+    let gaps: Vec<f64> = query
+        .iter()
+        .zip(lower.iter().zip(upper))
+        .map(|(value, (lower, upper))| gap(*value, *lower, *upper))
+        .collect();
+    // summed the same way as l2_distance, so that we get exactly the same
+    // result at the leaves
+    sum_pairs(&gaps, &gaps, |gap, _| gap * gap).sqrt()
+}
+
+// how far `value` is from [lower, upper] in one dimension. for a box that's a
+// single point this is the same difference l2_distance takes, NaNs included;
+// a box's bounds are only NaN when everything inside it is
+fn gap(value: f64, lower: f64, upper: f64) -> f64 {
+    if lower == upper || lower.is_nan() {
+        value - lower
+    } else if value < lower {
+        lower - value
+    } else if value > upper {
+        value - upper
+    } else {
+        0.0
+    }
+}
+
+fn bounds(key: &[f64]) -> (&[f64], &[f64]) {
+    key.split_at(key.len() / 2)
+}
+
+// the smallest box containing all of `keys`, of which there must be at least
+// one. NaN bounds are ignored, as f64::min and f64::max ignore NaNs
+fn union<'a>(mut keys: impl Iterator<Item = &'a [f64]>) -> Vec<f64> {
+    let mut union = keys.next().unwrap().to_vec();
+    let dims = union.len() / 2;
+    for key in keys {
+        if key.len() != union.len() {
+            error!(
+                "simple_array_gist_ops requires SimpleArrays of the same length, got {} and {}",
+                dims,
+                key.len() / 2
+            )
+        }
+        let (lower, upper) = union.split_at_mut(dims);
+        let (key_lower, key_upper) = bounds(key);
+        for (bound, value) in lower.iter_mut().zip(key_lower) {
+            *bound = bound.min(*value);
+        }
+        for (bound, value) in upper.iter_mut().zip(key_upper) {
+            *bound = bound.max(*value);
+        }
+    }
+    union
+}
+
+// the sum of the box's side lengths. unlike its volume this stays meaningful in
+// many dimensions, where the volume of most boxes is 0 or infinite
+fn margin(key: &[f64]) -> f64 {
+    let (lower, upper) = bounds(key);
+    lower
+        .iter()
+        .zip(upper)
+        .map(|(lower, upper)| upper - lower)
+        .filter(|side| !side.is_nan())
+        .sum()
+}
+
+unsafe fn key<'a>(datum: pg_sys::Datum) -> &'a [f64] {
+    SimpleArray::from_datum(datum, false, pg_sys::InvalidOid)
+        .unwrap()
+        .0
+        .data
+}
+
+unsafe fn entry_keys(entries: &pg_sys::GistEntryVector, start: usize) -> Vec<&[f64]> {
+    entries.vector.as_slice(entries.n as usize)[start..]
+        .iter()
+        .map(|entry| key(entry.key))
+        .collect()
+}
+
+// GiST expects the offsets in a palloc'd array
+unsafe fn offsets(indexes: &[usize]) -> *mut pg_sys::OffsetNumber {
+    let offsets = pg_sys::palloc(indexes.len() * size_of::<pg_sys::OffsetNumber>())
+        as *mut pg_sys::OffsetNumber;
+    for (i, index) in indexes.iter().enumerate() {
+        *offsets.add(i) = (index + FIRST_OFFSET_NUMBER) as pg_sys::OffsetNumber;
+    }
+    offsets
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_similarity() {
+        Spi::execute(|client| {
+            let (dot, negative_dot) = client.select("SELECT dot(a, b), a <#> b FROM (SELECT '[1.0, 2.0, 3.0]'::SimpleArray a, '[4.0, 5.0, 6.0]'::SimpleArray b) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(dot, Some(32.0));
+            assert_eq!(negative_dot, Some(-32.0));
+
+            let (distance, operator) = client.select("SELECT l2_distance(a, b), a <-> b FROM (SELECT '[0.0, 0.0]'::SimpleArray a, '[3.0, 4.0]'::SimpleArray b) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(distance, Some(5.0));
+            assert_eq!(operator, Some(5.0));
+
+            let (orthogonal, parallel) = client.select("SELECT '[1.0, 0.0]'::SimpleArray <=> '[0.0, 1.0]'::SimpleArray, cosine_distance('[0.0, 2.0]'::SimpleArray, '[0.0, 3.0]'::SimpleArray)", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(orthogonal, Some(1.0));
+            assert_eq!(parallel, Some(0.0));
+
+            let zero = client.select("SELECT cosine_distance('[0.0, 0.0]'::SimpleArray, '[1.0, 2.0]'::SimpleArray)", None, None)
+                .first()
+                .get_one::<f64>();
+            assert!(zero.unwrap().is_nan(), "{:?}", zero);
+
+            // long enough to use both the accumulators and the remainder
+            let dot = client.select("SELECT dot(a, a) FROM (SELECT simple_array(1.0) a FROM generate_series(1, 21)) d", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(dot, Some(21.0));
+        })
+    }
+
+    #[pg_test]
+    fn test_gist_nearest_neighbors() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE points (point SimpleArray)", None, None);
+            client.update("INSERT INTO points SELECT ('[' || i % 37 || ', ' || (i % 101) * 0.5 || ']')::SimpleArray FROM generate_series(1, 2000) i", None, None);
+            client.update("INSERT INTO points VALUES ('[NaN, 1.0]'), ('[inf, 0.0]'), ('[-0.0, 0.0]')", None, None);
+            client.update("CREATE INDEX points_knn ON points USING gist (point)", None, None);
+            client.update("SET enable_seqscan = off", None, None);
+
+            let plan = client.select("EXPLAIN SELECT point FROM points ORDER BY point <-> '[10.0, 20.0]' LIMIT 10", None, None)
+                .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert!(plan.contains("points_knn"), "{}", plan);
+
+            for query in &[
+                "SELECT (point <-> '[10.0, 20.25]')::text FROM points ORDER BY point <-> '[10.0, 20.25]' LIMIT 20",
+                "SELECT (point <-> '[-5.0, 100.0]')::text FROM points ORDER BY point <-> '[-5.0, 100.0]' LIMIT 3000",
+            ] {
+                client.update("SET enable_seqscan = off", None, None);
+                let indexed = client.select(query, None, None)
+                    .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                    .collect::<Vec<_>>();
+                client.update("SET enable_seqscan = on", None, None);
+                client.update("SET enable_indexscan = off", None, None);
+                let sequential = client.select(query, None, None)
+                    .map(|row| row.by_ordinal(1).unwrap().value::<String>().unwrap())
+                    .collect::<Vec<_>>();
+                client.update("SET enable_indexscan = on", None, None);
+                assert_eq!(indexed, sequential, "{}", query);
+            }
+        })
+    }
+
+    #[pg_test(error = "simple_array_gist_ops supports SimpleArrays of at most 500 elements, got 501")]
+    fn test_gist_too_many_dimensions() {
+        Spi::execute(|client| {
+            client.update("CREATE TABLE points (point SimpleArray)", None, None);
+            client.update("INSERT INTO points SELECT simple_array(1.0) FROM generate_series(1, 501)", None, None);
+            client.update("CREATE INDEX points_knn ON points USING gist (point)", None, None);
+        })
+    }
+
+    #[pg_test(error = "l2_distance requires SimpleArrays of the same length, got 2 and 3")]
+    fn test_similarity_length_mismatch() {
+        Spi::get_one::<f64>("SELECT '[1.0, 2.0]'::SimpleArray <-> '[1.0, 2.0, 3.0]'::SimpleArray");
+    }
+}
@@ -0,0 +1,184 @@
+use std::cmp::Ordering;
+
+use pgx::*;
+
+use crate::SimpleArray;
+
+// summary statistics computed directly over the packed data. as with the
+// corresponding postgres aggregates these return NULL for empty arrays, and
+// variance() and stddev() are the sample versions
+
+#[pg_extern(immutable, parallel_safe)]
+fn sum<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    let data = array.0.data;
+    if data.is_empty() {
+        return None;
+    }
+    Some(kahan_sum(data))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn mean<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    let data = array.0.data;
+    if data.is_empty() {
+        return None;
+    }
+    Some(kahan_sum(data) / data.len() as f64)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn min<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    array.0.data.iter().cloned().min_by(|a, b| float_cmp(*a, *b))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn max<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    array.0.data.iter().cloned().max_by(|a, b| float_cmp(*a, *b))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn variance<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    let (count, _, m2) = welford(array.0.data);
+    if count < 2.0 {
+        return None;
+    }
+    Some(m2 / (count - 1.0))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn stddev<'input>(array: SimpleArray<'input>) -> Option<f64> {
+    variance(array).map(f64::sqrt)
+}
+
+// the continuous percentile, interpolating between values like percentile_cont
+#[pg_extern(immutable, parallel_safe)]
+fn percentile<'input>(array: SimpleArray<'input>, p: f64) -> Option<f64> {
+    if !(0.0..=1.0).contains(&p) {
+        error!("percentile {} is not between 0 and 1", p)
+    }
+    let data = array.0.data;
+    if data.is_empty() {
+        return None;
+    }
+    let mut sorted = data.to_vec();
+    sorted.sort_by(|a, b| float_cmp(*a, *b));
+
+    let position = p * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    if lower == upper {
+        return Some(sorted[lower]);
+    }
+    let fraction = position - lower as f64;
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * fraction)
+}
+
+// kahan-babuska (neumaier) summation. the compensation turns infinities into
+// NaN, so when the plain sum isn't finite we return that instead
+fn kahan_sum(values: &[f64]) -> f64 {
+    let mut sum = 0.0f64;
+    let mut compensation = 0.0;
+    for &value in values {
+        let t = sum + value;
+        if sum.abs() >= value.abs() {
+            compensation += (sum - t) + value;
+        } else {
+            compensation += (value - t) + sum;
+        }
+        sum = t;
+    }
+    if !sum.is_finite() {
+        return sum;
+    }
+    sum + compensation
+}
+
+// welford's algorithm, returns the count, mean, and sum of squared differences
+fn welford(values: &[f64]) -> (f64, f64, f64) {
+    let mut count = 0.0;
+    let mut mean = 0.0;
+    let mut m2 = 0.0;
+    for &value in values {
+        count += 1.0;
+        let delta = value - mean;
+        mean += delta / count;
+        m2 += delta * (value - mean);
+    }
+    (count, mean, m2)
+}
+
+// orders floats the same way postgres does: NaN equals itself and is greater
+// than every other value, and -0.0 equals 0.0
+pub(crate) fn float_cmp(a: f64, b: f64) -> Ordering {
+    match a.partial_cmp(&b) {
+        Some(ordering) => ordering,
+        None => match (a.is_nan(), b.is_nan()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            _ => Ordering::Less,
+        },
+    }
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_summary_statistics() {
+        Spi::execute(|client| {
+            let array = "'[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]'::SimpleArray";
+
+            let (sum, mean) = client.select(&format!("SELECT sum({0}), mean({0})", array), None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(sum, Some(40.0));
+            assert_eq!(mean, Some(5.0));
+
+            let (min, max) = client.select(&format!("SELECT min({0}), max({0})", array), None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(min, Some(2.0));
+            assert_eq!(max, Some(9.0));
+
+            let (variance, stddev) = client.select(&format!("SELECT variance({0}), stddev({0})", array), None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(variance, Some(32.0 / 7.0));
+            assert_eq!(stddev, Some((32.0f64 / 7.0).sqrt()));
+
+            let (median, p90) = client.select(&format!("SELECT percentile({0}, 0.5), percentile({0}, 0.9)", array), None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(median, Some(4.5));
+            assert_eq!(p90, Some(7.6));
+        })
+    }
+
+    #[pg_test]
+    fn test_summary_statistics_edge_cases() {
+        Spi::execute(|client| {
+            let (sum, max) = client.select("SELECT sum(a), max(a) FROM (SELECT '[1e100, 1.0, -1e100, NaN]'::SimpleArray a) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert!(sum.unwrap().is_nan());
+            assert!(max.unwrap().is_nan());
+
+            let sum = client.select("SELECT sum('[1e100, 1.0, -1e100]'::SimpleArray)", None, None)
+                .first()
+                .get_one::<f64>();
+            assert_eq!(sum, Some(1.0));
+
+            let (mean, variance) = client.select("SELECT mean(a), variance(a) FROM (SELECT '[]'::SimpleArray a) d", None, None)
+                .first()
+                .get_two::<f64, f64>();
+            assert_eq!(mean, None);
+            assert_eq!(variance, None);
+        })
+    }
+
+    #[pg_test(error = "percentile 1.5 is not between 0 and 1")]
+    fn test_percentile_out_of_range() {
+        Spi::get_one::<f64>("SELECT percentile('[1.0]'::SimpleArray, 1.5)");
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+use pgx::*;
+
+use crate::{hashing::canonical, stats::float_cmp, SimpleArray};
+
+// functions that rearrange or filter the elements of a SimpleArray, producing
+// a new one. these work on a single copy of the data so they stay cheap for
+// large arrays
+
+// sorts in the same order as ORDER BY on float8, so NaNs come last, or first
+// if `descending` is set
+#[pg_extern(immutable, parallel_safe)]
+fn sort<'input>(
+    array: SimpleArray<'input>,
+    descending: default!(bool, false),
+) -> SimpleArray<'static> {
+    let mut values = array.0.data.to_vec();
+    if descending {
+        values.sort_by(|a, b| float_cmp(*b, *a));
+    } else {
+        values.sort_by(|a, b| float_cmp(*a, *b));
+    }
+    SimpleArray::from_values(&values)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn reverse<'input>(array: SimpleArray<'input>) -> SimpleArray<'static> {
+    let values: Vec<f64> = array.0.data.iter().rev().cloned().collect();
+    SimpleArray::from_values(&values)
+}
+
+// removes duplicates, keeping the first occurrence of each value. values are
+// duplicates if `=` considers them equal, so all NaNs are the same, as are
+// -0.0 and 0.0. DISTINCT is a reserved word, so this can't be called distinct()
+#[pg_extern(immutable, parallel_safe)]
+fn distinct_values<'input>(array: SimpleArray<'input>) -> SimpleArray<'static> {
+    let mut seen = HashSet::with_capacity(array.0.data.len());
+    let values: Vec<f64> = array
+        .0
+        .data
+        .iter()
+        .cloned()
+        .filter(|value| seen.insert(canonical(*value).to_bits()))
+        .collect();
+    SimpleArray::from_values(&values)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn filter_nan<'input>(array: SimpleArray<'input>) -> SimpleArray<'static> {
+    let values: Vec<f64> = array.0.data.iter().cloned().filter(|value| !value.is_nan()).collect();
+    SimpleArray::from_values(&values)
+}
+
+#[cfg(feature = "pg_test")]
+mod tests {
+    use pgx::*;
+
+    #[pg_test]
+    fn test_transforms() {
+        Spi::execute(|client| {
+            let results = client.select("SELECT sort(a)::text, sort(a, true)::text, reverse(a)::text FROM (SELECT '[2.0, NaN, -1.0, 3.0, 2.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(results, (
+                Some("[-1.0, 2.0, 2.0, 3.0, NaN]".to_string()),
+                Some("[NaN, 3.0, 2.0, 2.0, -1.0]".to_string()),
+                Some("[2.0, 3.0, -1.0, NaN, 2.0]".to_string()),
+            ));
+
+            let results = client.select("SELECT distinct_values(a)::text, filter_nan(a)::text, sort('[]'::SimpleArray)::text FROM (SELECT '[2.0, NaN, 0.0, -0.0, NaN, 2.0, 1.0]'::SimpleArray a) d", None, None)
+                .first()
+                .get_three::<String, String, String>();
+            assert_eq!(results, (
+                Some("[2.0, NaN, 0.0, 1.0]".to_string()),
+                Some("[2.0, 0.0, -0.0, 2.0, 1.0]".to_string()),
+                Some("[]".to_string()),
+            ));
+        })
+    }
+}